@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use payment_engine::{binary, Policy};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+fn formats(c: &mut Criterion) {
+    const LEN: u32 = 100_000;
+    let csv = common::generate_csv(LEN, 1000);
+    let mut bin = vec![];
+    binary::convert(payment_engine::read_input(csv.as_bytes()), &mut bin).unwrap();
+
+    let mut group = c.benchmark_group("formats");
+    group.throughput(Throughput::Elements(LEN as u64));
+    group.sample_size(20);
+
+    group.bench_function(BenchmarkId::new("read", "csv"), |b| {
+        b.iter(|| payment_engine::read_input(csv.as_bytes()).count())
+    });
+    group.bench_function(BenchmarkId::new("read", "bin"), |b| {
+        b.iter(|| binary::read_input(bin.as_slice()).count())
+    });
+    group.bench_function(BenchmarkId::new("read_txs", "bin"), |b| {
+        b.iter(|| binary::read_txs(bin.as_slice()).count())
+    });
+
+    let policy = Policy::default();
+    group.bench_function(BenchmarkId::new("run", "csv"), |b| {
+        b.iter(|| {
+            let rows = payment_engine::read_input(csv.as_bytes());
+            payment_engine::run_rows(rows, &policy, |_| Ok(())).unwrap()
+        })
+    });
+    group.bench_function(BenchmarkId::new("run", "bin"), |b| {
+        b.iter(|| {
+            let rows = binary::read_input(bin.as_slice());
+            payment_engine::run_rows(rows, &policy, |_| Ok(())).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, formats);
+criterion_main!(benches);
@@ -140,6 +140,22 @@ impl<'t, T> TokenProtected<'t, T> {
         Prepared::new(self, |_| Ok(()))
     }
 
+    /// Doesn't change `T`, and joins its token after the tokens of an
+    /// already applied modification.
+    ///
+    /// Has the same effect as chaining the modification with a `skip`,
+    /// but avoids creating a copy of `T`.
+    #[allow(clippy::type_complexity)]
+    pub fn skip_after<'t0, 'tboth, T0, E>(
+        self,
+        applied: Result<ConsumedToken<'t0, T0>, (E, Token<'t0, T0>)>,
+    ) -> Result<ConsumedToken<'tboth, (T0, T)>, (E, Token<'tboth, (T0, T)>)> {
+        match applied {
+            Ok(consumed) => Ok(consumed.then(self.consume())),
+            Err((e, token)) => Err((e, token.then(self.token))),
+        }
+    }
+
     /// Consumes the token without changing `T`.
     pub fn consume(self) -> ConsumedToken<'t, T> {
         self.token.consume()
@@ -0,0 +1,346 @@
+//! A compact binary encoding of `ExternalTx`s, for inputs where the csv
+//! parsing would dominate the processing.
+//!
+//! The input starts with the `MAGIC` bytes, followed by the records.
+//! Each record starts with its length (one byte, not counting itself),
+//! followed by:
+//! - the `TxType` tag (one byte, see `tag`);
+//! - the `ClientId` (u16);
+//! - the `TxId` (u32);
+//! - and, only if present, the `Amount` as an i64 of `10^-SCALE` units;
+//! - followed, for transfers, by the destination `ClientId` (u16);
+//! - or, for admin txs (which have no amount, except the limit), the
+//!   utf-8 reason, if any.
+//!
+//! All integers are little-endian, and only the default asset is supported,
+//! without expiries nor timestamps.
+
+use crate::{
+    tx::{ExternalTx, TxType},
+    types::{Amount, Asset},
+    InputRow,
+};
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+/// Identifies (and versions) the binary inputs.
+pub const MAGIC: &[u8; 4] = b"PEB1";
+/// Amount of decimals of the encoded amounts.
+pub const SCALE: u32 = 4;
+
+const LEN_WITHOUT_AMOUNT: u8 = 1 + 2 + 4;
+const LEN_WITH_AMOUNT: u8 = LEN_WITHOUT_AMOUNT + 8;
+const LEN_DESTINATION: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum EncodeError {
+    #[error("The amount {0} has more than {} decimals, or is too big", SCALE)]
+    UnrepresentableAmount(Amount),
+    #[error("The admin tx has the amount {0}, which can't be encoded")]
+    AdminAmount(Amount),
+    #[error("The reason has {0} bytes, but at most {1} are supported")]
+    ReasonTooLong(usize, usize),
+    #[error("The transfer has no destination, which can't be encoded")]
+    MissingDestination,
+    #[error("The tx has the asset {0}, but only the default one can be encoded")]
+    UnsupportedAsset(Asset),
+    #[error("The tx has an expiry or a timestamp, which can't be encoded")]
+    UnsupportedTime,
+    #[error("The limit tx has no amount, which can't be encoded")]
+    MissingLimit,
+}
+
+/// Errors from records that could be skipped over.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum DecodeError {
+    #[error("Unknown tx type tag {0}")]
+    UnknownTag(u8),
+    #[error("Invalid record length {0}")]
+    InvalidLength(u8),
+    #[error("The reason is not utf-8")]
+    InvalidReason,
+}
+
+fn tag(ty: &TxType) -> u8 {
+    match ty {
+        TxType::Deposit => 0,
+        TxType::Withdrawal => 1,
+        TxType::Dispute => 2,
+        TxType::Resolve => 3,
+        TxType::Chargeback => 4,
+        TxType::Transfer => 8,
+        TxType::Unlock => 5,
+        TxType::Freeze => 6,
+        TxType::Close => 7,
+        TxType::Authorize => 9,
+        TxType::Capture => 10,
+        TxType::Void => 11,
+        TxType::Limit => 12,
+    }
+}
+
+fn from_tag(tag: u8) -> Result<TxType, DecodeError> {
+    Ok(match tag {
+        0 => TxType::Deposit,
+        1 => TxType::Withdrawal,
+        2 => TxType::Dispute,
+        3 => TxType::Resolve,
+        4 => TxType::Chargeback,
+        5 => TxType::Unlock,
+        6 => TxType::Freeze,
+        7 => TxType::Close,
+        8 => TxType::Transfer,
+        9 => TxType::Authorize,
+        10 => TxType::Capture,
+        11 => TxType::Void,
+        12 => TxType::Limit,
+        tag => return Err(DecodeError::UnknownTag(tag)),
+    })
+}
+
+/// Writes `ExternalTx`s in the binary encoding.
+pub struct Writer<W: Write>(W);
+
+impl<W: Write> Writer<W> {
+    /// Starts the output by writing the `MAGIC` bytes.
+    pub fn new(mut wrt: W) -> io::Result<Self> {
+        wrt.write_all(MAGIC)?;
+        Ok(Self(wrt))
+    }
+
+    pub fn write(&mut self, tx: &ExternalTx) -> anyhow::Result<()> {
+        if let Some(asset) = &tx.asset {
+            return Err(EncodeError::UnsupportedAsset(asset.clone()).into());
+        }
+        if tx.expires_at.is_some() || tx.timestamp.is_some() {
+            return Err(EncodeError::UnsupportedTime.into());
+        }
+        if tx.ty.is_admin() {
+            return self.write_admin(tx);
+        }
+        let amount = match &tx.amount {
+            Some(amount) => match amount.to_scaled(SCALE) {
+                Some(scaled) => Some(scaled),
+                None => return Err(EncodeError::UnrepresentableAmount(amount.clone()).into()),
+            },
+            None => None,
+        };
+        let mut record = [0u8; 1 + (LEN_WITH_AMOUNT + LEN_DESTINATION) as usize];
+        record[1] = tag(&tx.ty);
+        record[2..4].copy_from_slice(&u16::from(tx.client.clone()).to_le_bytes());
+        record[4..8].copy_from_slice(&u32::from(tx.txid.clone()).to_le_bytes());
+        let mut len = match amount {
+            Some(amount) => {
+                record[8..16].copy_from_slice(&amount.to_le_bytes());
+                LEN_WITH_AMOUNT
+            }
+            None => LEN_WITHOUT_AMOUNT,
+        };
+        if tx.ty == TxType::Transfer {
+            let destination = match &tx.destination {
+                Some(destination) => u16::from(destination.clone()),
+                None => return Err(EncodeError::MissingDestination.into()),
+            };
+            let start = 1 + len as usize;
+            record[start..start + 2].copy_from_slice(&destination.to_le_bytes());
+            len += LEN_DESTINATION;
+        }
+        record[0] = len;
+        self.0.write_all(&record[..1 + len as usize])?;
+        Ok(())
+    }
+
+    fn write_admin(&mut self, tx: &ExternalTx) -> anyhow::Result<()> {
+        // only the limit has an amount, which is always present
+        let amount = match (&tx.ty, &tx.amount) {
+            (TxType::Limit, Some(amount)) => match amount.to_scaled(SCALE) {
+                Some(scaled) => Some(scaled),
+                None => return Err(EncodeError::UnrepresentableAmount(amount.clone()).into()),
+            },
+            (TxType::Limit, None) => return Err(EncodeError::MissingLimit.into()),
+            (_, Some(amount)) => return Err(EncodeError::AdminAmount(amount.clone()).into()),
+            (_, None) => None,
+        };
+        let header_len = match amount {
+            Some(_) => LEN_WITH_AMOUNT,
+            None => LEN_WITHOUT_AMOUNT,
+        } as usize;
+        let reason = tx.reason.as_deref().unwrap_or_default().as_bytes();
+        let max_len = u8::MAX as usize - header_len;
+        if reason.len() > max_len {
+            return Err(EncodeError::ReasonTooLong(reason.len(), max_len).into());
+        }
+        let len = header_len + reason.len();
+        let mut record = Vec::with_capacity(1 + len);
+        record.push(len as u8);
+        record.push(tag(&tx.ty));
+        record.extend_from_slice(&u16::from(tx.client.clone()).to_le_bytes());
+        record.extend_from_slice(&u32::from(tx.txid.clone()).to_le_bytes());
+        if let Some(amount) = amount {
+            record.extend_from_slice(&amount.to_le_bytes());
+        }
+        record.extend_from_slice(reason);
+        self.0.write_all(&record)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+/// Decodes the record, without its length prefix.
+fn decode(record: &[u8]) -> Result<ExternalTx, DecodeError> {
+    let len = record.len() as u8;
+    if len < LEN_WITHOUT_AMOUNT {
+        return Err(DecodeError::InvalidLength(len));
+    }
+    let ty = from_tag(record[0])?;
+    let client = u16::from_le_bytes([record[1], record[2]]);
+    let txid = u32::from_le_bytes([record[3], record[4], record[5], record[6]]);
+    if ty.is_admin() {
+        let (amount, reason) = if ty == TxType::Limit {
+            if len < LEN_WITH_AMOUNT {
+                return Err(DecodeError::InvalidLength(len));
+            }
+            let mut scaled = [0u8; 8];
+            scaled.copy_from_slice(&record[7..15]);
+            let amount = Amount::from_scaled(i64::from_le_bytes(scaled), SCALE);
+            (Some(amount), &record[LEN_WITH_AMOUNT as usize..])
+        } else {
+            (None, &record[LEN_WITHOUT_AMOUNT as usize..])
+        };
+        let reason = std::str::from_utf8(reason).map_err(|_| DecodeError::InvalidReason)?;
+        return Ok(ExternalTx {
+            ty,
+            client: client.into(),
+            txid: txid.into(),
+            amount,
+            destination: None,
+            asset: None,
+            reason: Some(reason.to_string()).filter(|reason| !reason.is_empty()),
+            expires_at: None,
+            timestamp: None,
+            row: None,
+        });
+    }
+    let (len, destination) = match ty {
+        TxType::Transfer if len >= LEN_WITHOUT_AMOUNT + LEN_DESTINATION => {
+            let len = len - LEN_DESTINATION;
+            let at = len as usize;
+            let destination = u16::from_le_bytes([record[at], record[at + 1]]);
+            (len, Some(destination.into()))
+        }
+        _ => (len, None),
+    };
+    if len != LEN_WITHOUT_AMOUNT && len != LEN_WITH_AMOUNT {
+        return Err(DecodeError::InvalidLength(record.len() as u8));
+    }
+    let amount = if len == LEN_WITH_AMOUNT {
+        let mut scaled = [0u8; 8];
+        scaled.copy_from_slice(&record[7..15]);
+        Some(Amount::from_scaled(i64::from_le_bytes(scaled), SCALE))
+    } else {
+        None
+    };
+    Ok(ExternalTx {
+        ty,
+        client: client.into(),
+        txid: txid.into(),
+        amount,
+        destination,
+        asset: None,
+        reason: None,
+        expires_at: None,
+        timestamp: None,
+        row: None,
+    })
+}
+
+/// A decoded record, and its index (starting from `1`).
+type Record = (u64, Result<ExternalTx, DecodeError>);
+
+/// Streams the decoded records from a reader.
+///
+/// Missing `MAGIC` bytes and a truncated record are yielded as errors.
+fn read_records<R: Read>(rdr: R) -> impl Iterator<Item = io::Result<Record>> {
+    let mut rdr = io::BufReader::new(rdr);
+    let mut started = false;
+    let mut index = 0;
+    let mut record = [0u8; u8::MAX as usize];
+    std::iter::from_fn(move || {
+        if !started {
+            started = true;
+            let mut magic = [0u8; 4];
+            if let Err(e) = rdr.read_exact(&mut magic) {
+                return Some(Err(e));
+            }
+            if &magic != MAGIC {
+                let e = io::Error::new(io::ErrorKind::InvalidData, "Not a binary tx input");
+                return Some(Err(e));
+            }
+        }
+        let mut len = [0u8; 1];
+        match rdr.read(&mut len) {
+            Ok(0) => return None,
+            Ok(_) => (),
+            Err(e) => return Some(Err(e)),
+        }
+        let record = &mut record[..len[0] as usize];
+        if let Err(e) = rdr.read_exact(record) {
+            return Some(Err(e));
+        }
+        index += 1;
+        Some(Ok((index, decode(record))))
+    })
+}
+
+/// Streams the transactions from a binary reader, which can be given to
+/// `try_run`.
+///
+/// Stops at the first record that can't be decoded.
+pub fn read_txs<R: Read>(rdr: R) -> impl Iterator<Item = io::Result<ExternalTx>> {
+    read_records(rdr).map(|record| {
+        let (_index, tx) = record?;
+        tx.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    })
+}
+
+/// Streams the rows from a binary reader, where the line of each row is
+/// the index of its record.
+///
+/// The rows have no raw text, see `InputRow::raw`.  
+/// A record that can't be decoded is yielded as a malformed row, same as
+/// for `read_input`.
+pub fn read_input<R: Read>(rdr: R) -> impl Iterator<Item = csv::Result<InputRow>> {
+    read_records(rdr).map(|record| {
+        let (line, tx) = record?;
+        let tx = tx.map_err(|e| csv::Error::from(io::Error::new(io::ErrorKind::InvalidData, e)));
+        // only rendered if the row gets rejected, see `InputRow::raw`
+        let raw = String::new();
+        Ok(InputRow { line, raw, tx })
+    })
+}
+
+/// Writes all of the rows into the binary encoding, returning the amount
+/// of written transactions.
+///
+/// Stops at the first row that is malformed or can't be encoded.
+pub fn convert<W: Write>(
+    rows: impl Iterator<Item = csv::Result<InputRow>>,
+    wrt: W,
+) -> anyhow::Result<u64> {
+    let mut writer = Writer::new(wrt)?;
+    let mut written = 0;
+    for row in rows {
+        let InputRow { line, tx, .. } = row?;
+        let tx = tx.map_err(|e| anyhow::anyhow!("Line {}: {}", line, e))?;
+        writer
+            .write(&tx)
+            .map_err(|e| anyhow::anyhow!("Line {}: {}", line, e))?;
+        written += 1;
+    }
+    writer.flush()?;
+    Ok(written)
+}
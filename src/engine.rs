@@ -0,0 +1,439 @@
+use crate::{
+    storage::{self, Storage},
+    tx::{InternalTxId, Tx, TxError, TxId},
+    types::ClientId,
+    Client, Clients, ExternalTx, InputRow, Policy, Reject, Snapshot, TxType, Txs, TP,
+};
+use std::{borrow::Cow, collections::BTreeSet};
+use tracing::{error, info};
+
+/// The result of a transaction that was processed without errors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The transaction changed the engine's state.
+    Applied(InternalTxId),
+    /// The transaction was an exact resubmission of a stored one, and
+    /// was accepted without changes.
+    ///
+    /// See `Policy::idempotent_replays`.
+    Replayed(InternalTxId),
+}
+
+impl Outcome {
+    pub fn internal_txid(&self) -> &InternalTxId {
+        match self {
+            Outcome::Applied(internal_txid) | Outcome::Replayed(internal_txid) => internal_txid,
+        }
+    }
+}
+
+/// The pending holds of the stored authorize txs that may expire,
+/// ordered by when they are due.
+///
+/// Holds that got settled in the meantime are skipped when due.
+#[derive(Debug, Default)]
+struct Holds {
+    /// By the authorize's id, see `Policy::hold_expiry`.
+    by_count: BTreeSet<(InternalTxId, TxId)>,
+    /// By the authorize's `expires_at`.
+    by_time: BTreeSet<(u64, TxId)>,
+}
+
+impl Holds {
+    fn track(&mut self, tx: &Tx, policy: &Policy) {
+        if tx.ty != TxType::Authorize || tx.is_settled() {
+            return;
+        }
+        if policy.hold_expiry.is_some() {
+            let hold = (tx.internal_txid.clone(), tx.txid.clone());
+            self.by_count.insert(hold);
+        }
+        if let Some(expires_at) = tx.expires_at {
+            self.by_time.insert((expires_at, tx.txid.clone()));
+        }
+    }
+
+    /// Removes and returns the holds that are due before the tx
+    /// `internal_txid` (at the `timestamp`, if any).
+    fn take_due(
+        &mut self,
+        internal_txid: &InternalTxId,
+        timestamp: Option<u64>,
+        policy: &Policy,
+    ) -> Vec<TxId> {
+        let mut due = vec![];
+        if let Some(expiry) = policy.hold_expiry {
+            let current = u32::from(internal_txid.clone());
+            while let Some(hold) = self.by_count.iter().next().cloned() {
+                let (authorized, txid) = &hold;
+                if current.saturating_sub(u32::from(authorized.clone())) <= expiry {
+                    break;
+                }
+                due.push(txid.clone());
+                self.by_count.remove(&hold);
+            }
+        }
+        if let Some(timestamp) = timestamp {
+            while let Some(hold) = self.by_time.iter().next().cloned() {
+                let (expires_at, txid) = &hold;
+                if *expires_at > timestamp {
+                    break;
+                }
+                due.push(txid.clone());
+                self.by_time.remove(&hold);
+            }
+        }
+        due
+    }
+}
+
+/// The engine's state while transactions are being processed.
+///
+/// Transactions may be processed one at a time, and the clients and
+/// stored transactions can be inspected in between.
+///
+/// The holds of authorize txs expire lazily, being voided before the
+/// next tx is processed.
+#[derive(Debug, Default)]
+pub struct Engine {
+    clients: Clients,
+    txs: Txs,
+    internal_txid: InternalTxId,
+    policy: Policy,
+    holds: Holds,
+}
+
+impl Engine {
+    pub fn new(policy: Policy) -> Self {
+        let mut engine = Self {
+            policy,
+            ..Self::default()
+        };
+        // so the house is shown in the outputs, even without fees
+        if let Some(fees) = &engine.policy.fees {
+            let house = engine.policy.new_client(&fees.house);
+            engine.clients.insert(house.id.clone(), house);
+        }
+        engine
+    }
+
+    /// Starts from the state of a previously exported `Snapshot`.
+    pub fn from_snapshot(snapshot: Snapshot, policy: Policy) -> anyhow::Result<Self> {
+        snapshot.check_version()?;
+        let mut engine = Self::new(policy);
+        engine.internal_txid = snapshot.next_internal_txid;
+        for client in snapshot.clients {
+            engine.clients.insert(client.id.clone(), client);
+        }
+        for tx in snapshot.txs {
+            engine.holds.track(&tx, &engine.policy);
+            engine.txs.insert(tx)?;
+        }
+        Ok(engine)
+    }
+
+    /// Loads the state persisted in the `storage`, re-processing the
+    /// transactions logged after its last snapshot.
+    pub fn recover(policy: Policy, storage: &mut impl Storage) -> anyhow::Result<Self> {
+        let (snapshot, entries) = storage.recover()?;
+        let mut engine = match snapshot {
+            Some(snapshot) => Self::from_snapshot(snapshot, policy)?,
+            None => Self::new(policy),
+        };
+        for entry in entries {
+            // the entries may have been logged before the snapshot
+            if entry.internal_txid < engine.internal_txid {
+                continue;
+            }
+            engine.internal_txid = entry.internal_txid.clone();
+            let tx = match &entry.tx {
+                Some(tx) => tx,
+                // only the position of a rejected row was kept
+                None => {
+                    engine.skip();
+                    continue;
+                }
+            };
+            match (engine.process(tx), entry.rejected) {
+                (Ok(_), false) | (Err(_), true) => (),
+                (Ok(_), true) => anyhow::bail!("A logged rejected transaction got applied"),
+                (Err(e), false) => {
+                    anyhow::bail!("Failed to re-process a logged transaction. {}", e)
+                }
+            }
+        }
+        Ok(engine)
+    }
+
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    pub fn client(&self, id: &ClientId) -> Option<&Client> {
+        self.clients.get(id)
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+
+    /// A stored deposit or withdrawal, including its dispute status.
+    pub fn tx(&self, txid: &TxId) -> Option<&Tx> {
+        self.txs.get(txid)
+    }
+
+    /// The id that the next incoming transaction will receive.
+    pub fn next_internal_txid(&self) -> &InternalTxId {
+        &self.internal_txid
+    }
+
+    /// Processes a single transaction.
+    ///
+    /// The transaction is ignored in case of an error.
+    pub fn process(&mut self, cltx: &ExternalTx) -> Result<Outcome, TxError> {
+        let internal_txid = self.skip();
+        self.process_as(cltx, internal_txid)
+    }
+
+    /// Same as `process`, but the `InternalTxId` is given instead of
+    /// being stepped.
+    pub(crate) fn process_as(
+        &mut self,
+        cltx: &ExternalTx,
+        internal_txid: InternalTxId,
+    ) -> Result<Outcome, TxError> {
+        self.expire_holds(&internal_txid, cltx.timestamp);
+
+        let id = &cltx.client;
+        let policy = &self.policy;
+        self.clients
+            .entry(id.clone())
+            .or_insert_with(|| policy.new_client(id));
+
+        // the rounded amount is the one stored
+        let cltx = match &self.policy.precision {
+            Some(precision) => cltx
+                .with_precision(precision)
+                .map_err(|e| cltx.client_error(e, internal_txid.clone()))?,
+            None => Cow::Borrowed(cltx),
+        };
+        let cltx = cltx.as_ref();
+        // checked after the rounding, which could turn it into zero
+        cltx.check_amount()
+            .map_err(|e| cltx.client_error(e, internal_txid.clone()))?;
+
+        // the other client of a transfer, or of a disputed one
+        let destination = match cltx.ty {
+            TxType::Transfer => cltx.destination.clone(),
+            TxType::Dispute | TxType::Resolve | TxType::Chargeback => self
+                .txs
+                .get(&cltx.txid)
+                .filter(|tx| tx.ty == TxType::Transfer)
+                .and_then(|tx| tx.destination.clone()),
+            _ => None,
+        }
+        .filter(|destination| destination != id);
+
+        // the fee of a withdrawal, or of the chargeback of a stored tx
+        let fee = match (&destination, &cltx.ty) {
+            (None, TxType::Withdrawal) => cltx.amount.as_ref(),
+            (None, TxType::Chargeback) => {
+                self.txs.get(&cltx.txid).and_then(|tx| tx.amount.as_ref())
+            }
+            _ => None,
+        }
+        .map(|amount| self.policy.fee(id, &cltx.ty, amount))
+        .transpose()
+        .map_err(|e| cltx.client_error(e, internal_txid.clone()))?
+        .flatten();
+
+        let protected_txs = TP::new(&mut self.txs);
+        let processed = match (&destination, &fee) {
+            (Some(destination), _) => {
+                // taken out, so both clients can be protected at once
+                let mut other = self
+                    .clients
+                    .remove(destination)
+                    .unwrap_or_else(|| policy.new_client(destination));
+                let client = self.clients.get_mut(id).unwrap();
+                let processed = Client::try_process_transfer(
+                    TP::new(client),
+                    TP::new(&mut other),
+                    cltx,
+                    protected_txs,
+                    &self.policy,
+                )
+                .map(drop)
+                .map_err(|(e, _tokens)| e);
+                self.clients.insert(destination.clone(), other);
+                processed
+            }
+            (None, Some(fee)) => {
+                // same as for transfers, with the house
+                let house_id = &self.policy.fees.as_ref().unwrap().house;
+                let mut house = self
+                    .clients
+                    .remove(house_id)
+                    .unwrap_or_else(|| policy.new_client(house_id));
+                let client = self.clients.get_mut(id).unwrap();
+                let processed = Client::try_process_with_fee(
+                    TP::new(client),
+                    TP::new(&mut house),
+                    cltx,
+                    protected_txs,
+                    &self.policy,
+                    fee,
+                )
+                .map(drop)
+                .map_err(|(e, _tokens)| e);
+                self.clients.insert(house_id.clone(), house);
+                processed
+            }
+            (None, None) => {
+                let client = self.clients.get_mut(id).unwrap();
+                Client::try_process_transaction(TP::new(client), cltx, protected_txs, &self.policy)
+                    .map(drop)
+                    .map_err(|(e, _tokens)| e)
+            }
+        };
+        processed.map_err(|e| cltx.client_error(e, internal_txid.clone()))?;
+
+        match cltx.ty {
+            TxType::Deposit | TxType::Withdrawal | TxType::Transfer | TxType::Authorize => {
+                let tx = Tx::from_external(cltx, internal_txid.clone());
+                self.holds.track(&tx, &self.policy);
+                let stored = self.txs.insert(tx);
+                if let Err(e) = stored {
+                    // only a replay of a stored tx could be accepted
+                    info!("Replay accepted as a no-op. {}", e);
+                    return Ok(Outcome::Replayed(internal_txid));
+                }
+            }
+            TxType::Dispute | TxType::Resolve | TxType::Capture | TxType::Void => (),
+            TxType::Chargeback => {
+                // the reversed client of a transfer is its destination
+                let reversed = destination.as_ref().unwrap_or(id);
+                let debt = self.clients.get(reversed).and_then(Client::debt);
+                if let Some(debt) = debt {
+                    info!("Client {:?} is in debt of {}", reversed, debt);
+                }
+            }
+            TxType::Unlock | TxType::Freeze | TxType::Close | TxType::Limit => {
+                // the audit trail of the admin txs
+                info!(
+                    "Client {:?} {}: {}",
+                    cltx.client,
+                    cltx.ty.as_str(),
+                    cltx.reason.as_deref().unwrap_or_default()
+                );
+            }
+        }
+        Ok(Outcome::Applied(internal_txid))
+    }
+
+    /// Voids the holds that are due before the tx `internal_txid`.
+    pub(crate) fn expire_holds(&mut self, internal_txid: &InternalTxId, timestamp: Option<u64>) {
+        for txid in self.holds.take_due(internal_txid, timestamp, &self.policy) {
+            let client = match self.txs.get(&txid) {
+                Some(tx) if !tx.is_settled() => tx.client.clone(),
+                _ => continue,
+            };
+            let void = ExternalTx {
+                ty: TxType::Void,
+                client: client.clone(),
+                txid: txid.clone(),
+                amount: None,
+                destination: None,
+                asset: None,
+                reason: None,
+                expires_at: None,
+                timestamp: None,
+                row: None,
+            };
+            let client = self.clients.get_mut(&client).unwrap();
+            let protected_txs = TP::new(&mut self.txs);
+            match Client::try_process_transaction(
+                TP::new(client),
+                &void,
+                protected_txs,
+                &self.policy,
+            ) {
+                Ok(_tokens) => info!("Hold {:?} of client {:?} expired", txid, void.client),
+                Err((e, _tokens)) => error!("Hold {:?} failed to expire. {}", txid, e),
+            }
+        }
+    }
+
+    /// Processes all of the rows, persisting the processed transactions
+    /// and the positions of the rejected rows into the `storage`.
+    pub(crate) fn process_rows(
+        &mut self,
+        rows: impl Iterator<Item = csv::Result<InputRow>>,
+        storage: &mut impl Storage,
+        mut on_reject: impl FnMut(Reject) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        for row in rows {
+            let row = row?;
+            let (entry, reject) = match &row.tx {
+                Ok(cltx) => match self.process(cltx) {
+                    Ok(outcome) => {
+                        let internal_txid = outcome.internal_txid().clone();
+                        (storage::WalEntry::new(internal_txid, cltx.clone()), None)
+                    }
+                    Err(e) => {
+                        let internal_txid = e.internal_txid().clone();
+                        // as its timestamp may still have expired some holds
+                        let tx = cltx.timestamp.is_some().then(|| cltx.clone());
+                        let entry = storage::WalEntry::rejected(internal_txid, tx);
+                        (entry, Some(Reject::from_tx_error(&row, &e)))
+                    }
+                },
+                Err(e) => {
+                    let internal_txid = self.skip();
+                    let entry = storage::WalEntry::rejected(internal_txid.clone(), None);
+                    (entry, Some(Reject::from_malformed(&row, internal_txid, e)))
+                }
+            };
+            // so a resumed execution doesn't reject the same rows again
+            storage.append(&entry)?;
+            if storage.wants_snapshot() {
+                storage.snapshot(&self.snapshot())?;
+            }
+            if let Some(reject) = reject {
+                on_reject(reject)?;
+            }
+        }
+        storage.sync()?;
+        Ok(())
+    }
+
+    /// Steps the `InternalTxId` without processing any transaction,
+    /// returning the skipped id.
+    pub(crate) fn skip(&mut self) -> InternalTxId {
+        let internal_txid = self.internal_txid.clone();
+        self.internal_txid.step();
+        internal_txid
+    }
+
+    /// Copies the state into a `Snapshot`.
+    ///
+    /// The clients and transactions are ordered, so the same state
+    /// always produces the same snapshot.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut clients: Vec<Client> = self.clients.values().cloned().collect();
+        clients.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut txs: Vec<Tx> = self.txs.iter().cloned().collect();
+        txs.sort_by(|a, b| a.internal_txid.cmp(&b.internal_txid));
+        Snapshot {
+            version: Snapshot::VERSION,
+            next_internal_txid: self.internal_txid.clone(),
+            clients,
+            txs,
+        }
+    }
+
+    /// Ends the processing, yielding the clients.
+    pub fn finish(self) -> Clients {
+        self.clients
+    }
+}
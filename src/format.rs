@@ -0,0 +1,135 @@
+//! The formats of the transactions input and of the clients output.
+
+use crate::{Client, ExternalTx, InputRow, OutputRow};
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+    str::FromStr,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Rows with a header, see `read_input` and `write_output`.
+    #[default]
+    Csv,
+    /// One json object per line, without a header.
+    JsonLines,
+    /// See the `binary` module. Only supported for the input.
+    Binary,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("Unknown format {0:?}, expected csv, jsonl or bin")]
+pub struct UnknownFormatError(String);
+
+impl FromStr for Format {
+    type Err = UnknownFormatError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "jsonl" | "ndjson" => Ok(Format::JsonLines),
+            "bin" => Ok(Format::Binary),
+            other => Err(UnknownFormatError(other.to_string())),
+        }
+    }
+}
+
+impl Format {
+    /// Streams the rows from a reader.
+    ///
+    /// See `read_input` for how malformed rows and errors are yielded.
+    pub fn read_input<'r, R: io::Read + 'r>(
+        self,
+        rdr: R,
+    ) -> Box<dyn Iterator<Item = csv::Result<InputRow>> + 'r> {
+        match self {
+            Format::Csv => Box::new(crate::read_input(rdr)),
+            Format::JsonLines => Box::new(read_json_lines(rdr)),
+            Format::Binary => Box::new(crate::binary::read_input(rdr)),
+        }
+    }
+
+    /// Streams the rows from a file.
+    ///
+    /// The path `-` indicates the stdin.
+    pub fn read_input_file(
+        self,
+        path: &Path,
+    ) -> anyhow::Result<Box<dyn Iterator<Item = csv::Result<InputRow>>>> {
+        let rdr: Box<dyn io::Read> = if path == Path::new("-") {
+            Box::new(io::stdin())
+        } else {
+            Box::new(std::fs::File::open(path)?)
+        };
+        Ok(self.read_input(rdr))
+    }
+
+    /// Streams the rows from many files, in sequence, as if they were a
+    /// single input.
+    ///
+    /// All files are opened upfront, so a missing file is reported before
+    /// any transaction gets processed.
+    pub fn read_input_files<P: AsRef<Path>>(
+        self,
+        paths: impl IntoIterator<Item = P>,
+    ) -> anyhow::Result<impl Iterator<Item = csv::Result<InputRow>>> {
+        let mut inputs = vec![];
+        for path in paths {
+            inputs.push(self.read_input_file(path.as_ref())?);
+        }
+        Ok(inputs.into_iter().flatten())
+    }
+
+    pub fn write_output<W: io::Write>(
+        self,
+        clients: impl Iterator<Item = Client>,
+        wrt: W,
+    ) -> anyhow::Result<()> {
+        match self {
+            Format::Csv => crate::write_output(clients, wrt),
+            Format::JsonLines => write_json_lines(clients, wrt),
+            Format::Binary => anyhow::bail!("The binary format is only supported for the input"),
+        }
+    }
+}
+
+/// Streams the rows from a json lines reader, skipping the empty lines.
+///
+/// A line that isn't a transaction is yielded with an error (converted
+/// into a `csv::Error`, so it's reported like a malformed csv row).
+fn read_json_lines<R: io::Read>(rdr: R) -> impl Iterator<Item = csv::Result<InputRow>> {
+    let mut lines = io::BufReader::new(rdr).lines();
+    let mut line = 0;
+    std::iter::from_fn(move || loop {
+        line += 1;
+        let raw = match lines.next()? {
+            Ok(raw) => raw,
+            Err(e) => return Some(Err(csv::Error::from(e))),
+        };
+        let raw = raw.trim();
+        if raw.is_empty() {
+            continue;
+        }
+        let tx = serde_json::from_str::<ExternalTx>(raw).map_err(|e| {
+            csv::Error::from(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+        });
+        return Some(Ok(InputRow {
+            line,
+            raw: raw.to_string(),
+            tx,
+        }));
+    })
+}
+
+fn write_json_lines<W: io::Write>(
+    clients: impl Iterator<Item = Client>,
+    wrt: W,
+) -> anyhow::Result<()> {
+    let mut wrt = io::BufWriter::new(wrt);
+    for row in OutputRow::all(clients.collect()) {
+        serde_json::to_writer(&mut wrt, &row)?;
+        wrt.write_all(b"\n")?;
+    }
+    wrt.flush()?;
+    Ok(())
+}
@@ -0,0 +1,207 @@
+//! A HTTP/JSON API over a shared `Engine`.
+//!
+//! - `POST /transactions` processes a json `ExternalTx`, such as
+//!   `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`.
+//! - `GET /clients/<id>` answers the json rows of the client, which are
+//!   the same as its rows of the batch output (see `OutputRow::all`).
+//! - `GET /transactions/<id>` answers a json `Tx`, including whether it's
+//!   disputed.
+//! - `GET /clients` answers all clients, in the same csv format as the
+//!   batch output.
+//!
+//! Errors are answered as `{"code": "E_...", "reason": "..."}`, where a
+//! rejected transaction has the same codes as the rejects csv.
+
+use crate::{
+    serve::Status,
+    tx::{InternalTxId, TxId},
+    types::ClientId,
+    Engine, ExternalTx, OutputRow,
+};
+use serde::Serialize;
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{Arc, Mutex},
+};
+use tiny_http::{Header, Method, Request, Response};
+use tracing::{error, info, warn};
+
+/// Code for requests that couldn't be understood.
+pub const BAD_REQUEST: &str = "E_BAD_REQUEST";
+/// Code for unknown paths, clients and transactions.
+pub const NOT_FOUND: &str = "E_NOT_FOUND";
+
+#[derive(Debug, Serialize)]
+struct ErrorReply {
+    code: &'static str,
+    reason: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TxReply {
+    #[serde(rename = "internal_tx")]
+    internal_txid: InternalTxId,
+    status: Status,
+    #[serde(flatten)]
+    error: Option<ErrorReply>,
+}
+
+/// A response's status code, content type and body.
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn json(status: u16, value: &impl Serialize) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self {
+                status,
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Self::text(500, e.to_string()),
+        }
+    }
+
+    fn error(status: u16, code: &'static str, reason: impl Into<String>) -> Self {
+        let reason = reason.into();
+        Self::json(status, &ErrorReply { code, reason })
+    }
+
+    fn text(status: u16, body: String) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: body.into_bytes(),
+        }
+    }
+}
+
+/// Answers requests, querying and processing into a shared `Engine`.
+pub struct HttpServer {
+    server: tiny_http::Server,
+    engine: Arc<Mutex<Engine>>,
+}
+
+impl HttpServer {
+    /// The `engine` may be shared with other servers, such as a
+    /// `serve::Server`.
+    pub fn bind(addr: impl ToSocketAddrs, engine: Arc<Mutex<Engine>>) -> anyhow::Result<Self> {
+        let server = tiny_http::Server::http(addr).map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok(Self { server, engine })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.server_addr()
+    }
+
+    /// Answers requests forever, one at a time.
+    pub fn run(self) {
+        info!("Listening for HTTP on {:?}", self.local_addr());
+        for request in self.server.incoming_requests() {
+            if let Err(e) = answer(request, &self.engine) {
+                warn!("Failed to answer a request. {}", e);
+            }
+        }
+    }
+}
+
+fn answer(mut request: Request, engine: &Mutex<Engine>) -> anyhow::Result<()> {
+    let mut body = vec![];
+    request.as_reader().read_to_end(&mut body)?;
+    // the query is ignored
+    let path = request.url().split('?').next().unwrap_or_default();
+    let reply = route(engine, request.method(), path, &body);
+    let content_type = Header::from_bytes(&b"Content-Type"[..], reply.content_type.as_bytes())
+        .map_err(|()| anyhow::anyhow!("Invalid content type"))?;
+    let response = Response::from_data(reply.body)
+        .with_status_code(reply.status)
+        .with_header(content_type);
+    request.respond(response)?;
+    Ok(())
+}
+
+fn route(engine: &Mutex<Engine>, method: &Method, path: &str, body: &[u8]) -> Reply {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match (method, segments.as_slice()) {
+        (Method::Post, ["transactions"]) => post_tx(engine, body),
+        (Method::Get, ["transactions", txid]) => match txid.parse::<u32>() {
+            Ok(txid) => {
+                let engine = lock(engine);
+                match engine.tx(&TxId::from(txid)) {
+                    Some(tx) => Reply::json(200, tx),
+                    None => Reply::error(404, NOT_FOUND, "Transaction not found"),
+                }
+            }
+            Err(e) => Reply::error(400, BAD_REQUEST, format!("Invalid tx id. {}", e)),
+        },
+        (Method::Get, ["clients", client]) => match client.parse::<u16>() {
+            Ok(client) => {
+                let engine = lock(engine);
+                match engine.client(&ClientId::from(client)) {
+                    Some(client) => {
+                        let client = engine.policy().render(client.clone());
+                        Reply::json(200, &OutputRow::all(vec![client]))
+                    }
+                    None => Reply::error(404, NOT_FOUND, "Client not found"),
+                }
+            }
+            Err(e) => Reply::error(400, BAD_REQUEST, format!("Invalid client id. {}", e)),
+        },
+        (Method::Get, ["clients"]) => {
+            let engine = lock(engine);
+            let clients: Vec<_> = engine
+                .clients()
+                .map(|client| engine.policy().render(client.clone()))
+                .collect();
+            drop(engine);
+            let mut body = vec![];
+            match crate::write_output(clients.into_iter(), &mut body) {
+                Ok(()) => Reply {
+                    status: 200,
+                    content_type: "text/csv",
+                    body,
+                },
+                Err(e) => Reply::text(500, e.to_string()),
+            }
+        }
+        _ => Reply::error(404, NOT_FOUND, format!("Unknown path {} {}", method, path)),
+    }
+}
+
+fn post_tx(engine: &Mutex<Engine>, body: &[u8]) -> Reply {
+    let cltx: ExternalTx = match serde_json::from_slice(body) {
+        Ok(cltx) => cltx,
+        Err(e) => return Reply::error(400, BAD_REQUEST, format!("Invalid transaction. {}", e)),
+    };
+    let result = lock(engine).process(&cltx);
+    match result {
+        Ok(outcome) => {
+            let reply = TxReply {
+                internal_txid: outcome.internal_txid().clone(),
+                status: Status::from(&outcome),
+                error: None,
+            };
+            Reply::json(200, &reply)
+        }
+        Err(e) => {
+            error!("{}", e);
+            let error = ErrorReply {
+                code: e.code(),
+                reason: e.to_string(),
+            };
+            let reply = TxReply {
+                internal_txid: e.internal_txid().clone(),
+                status: Status::Rejected,
+                error: Some(error),
+            };
+            Reply::json(422, &reply)
+        }
+    }
+}
+
+fn lock(engine: &Mutex<Engine>) -> std::sync::MutexGuard<'_, Engine> {
+    engine.lock().expect("Another request panicked")
+}
@@ -1,67 +1,220 @@
 // pub mod apply;
 pub mod apply;
+pub mod binary;
+pub mod engine;
+pub mod format;
+pub mod http;
+pub mod parallel;
+pub mod policy;
+pub mod reject;
+pub mod serve;
+pub mod snapshot;
+pub mod storage;
 pub mod types;
 
 pub use apply::{Apply, Prepared, TResult, Token, TokenProtected as TP};
-use std::collections::HashMap;
-use tracing::error;
+pub use engine::{Engine, Outcome};
+pub use format::Format;
+pub use policy::Policy;
+pub use reject::Reject;
+pub use snapshot::Snapshot;
+pub use storage::Storage;
+use tracing::{error, info};
 pub use types::{
-    client::{self, Client, Clients},
-    tx::{self, ExternalTx, OrderedTxs, TxType},
+    client::{self, AccountStatus, AssetRow, Balance, Client, ClientRow, Clients, OutputRow},
+    tx::{self, ExternalTx, TxType, Txs},
 };
 
 pub fn run(inputs: impl Iterator<Item = ExternalTx>) -> Clients {
-    let mut clients: Clients = HashMap::new();
-    let mut txs = OrderedTxs::from(vec![]);
-    let mut internal_txid = tx::InternalTxId::default();
+    match try_run(inputs.map(Ok::<_, std::convert::Infallible>)) {
+        Ok(clients) => clients,
+        Err(never) => match never {},
+    }
+}
 
+/// Same as `run`, but the inputs may fail to be produced, such as when
+/// they are being streamed from a file.
+///
+/// Stops at the first failed input, returning its error.
+pub fn try_run<E>(inputs: impl Iterator<Item = Result<ExternalTx, E>>) -> Result<Clients, E> {
+    let mut engine = Engine::default();
     for cltx in inputs.into_iter() {
-        let id = &cltx.client;
-        #[allow(clippy::or_fun_call)]
-        let client = clients.entry(id.clone()).or_insert(Client::new(id));
+        if let Err(e) = engine.process(&cltx?) {
+            error!("{}", e);
+        }
+    }
+    Ok(engine.finish())
+}
 
-        let protected_client = TP::new(client);
-        let protected_txs = TP::new(&mut txs);
+/// Same as `run`, but for rows read from a csv input, and with a
+/// custom `Policy`.
+///
+/// Rows that couldn't be read and transactions that were ignored are
+/// both reported into `on_reject`, and the processing continues.  
+/// Stops at the first input error (such as an IO error), or at the
+/// first error from `on_reject`.
+pub fn run_rows(
+    rows: impl Iterator<Item = csv::Result<InputRow>>,
+    policy: &Policy,
+    on_reject: impl FnMut(Reject) -> anyhow::Result<()>,
+) -> anyhow::Result<Clients> {
+    run_rows_with_storage(rows, policy, &mut storage::NoStorage, on_reject)
+}
 
-        match Client::try_process_transaction(protected_client, &cltx, protected_txs)
-            .map_err(|(e, _tokens)| cltx.client_error(e, internal_txid.clone()))
-        {
-            Ok(_consumed_tokens) => match cltx.ty {
-                TxType::Deposit | TxType::Withdrawal => {
-                    txs.push_ordered(tx::Tx::from_external(&cltx, internal_txid.clone()));
-                }
-                TxType::Dispute | TxType::Resolve | TxType::Chargeback => (),
-            },
-            Err(e) => {
-                error!("{}", e);
-            }
-        }
-        internal_txid.step();
+/// Same as `run_rows`, but the state is recovered from, and persisted
+/// into, the `storage`.
+///
+/// The rows that were already processed before the recovered state
+/// are skipped, so the same inputs should be given when resuming.  
+/// Also stops at the first error from `storage`.
+pub fn run_rows_with_storage(
+    rows: impl Iterator<Item = csv::Result<InputRow>>,
+    policy: &Policy,
+    storage: &mut impl Storage,
+    on_reject: impl FnMut(Reject) -> anyhow::Result<()>,
+) -> anyhow::Result<Clients> {
+    let mut engine = Engine::recover(policy.clone(), storage)?;
+    let processed = u32::from(engine.next_internal_txid().clone()) as usize;
+    if processed > 0 {
+        info!("Resuming after {} rows", processed);
     }
+    engine.process_rows(rows.skip(processed), storage, on_reject)?;
+    Ok(engine.finish())
+}
+
+/// Same as `run_rows`, but starts from the state of a previously
+/// exported `Snapshot`, and returns the final state as a `Snapshot`.
+///
+/// Differently from resuming from a `Storage`, no row is skipped, as
+/// the rows are considered to be new ones.
+pub fn run_rows_from_snapshot(
+    snapshot: Snapshot,
+    rows: impl Iterator<Item = csv::Result<InputRow>>,
+    policy: &Policy,
+    on_reject: impl FnMut(Reject) -> anyhow::Result<()>,
+) -> anyhow::Result<Snapshot> {
+    let mut engine = Engine::from_snapshot(snapshot, policy.clone())?;
+    engine.process_rows(rows, &mut storage::NoStorage, on_reject)?;
+    Ok(engine.snapshot())
+}
+
+/// A row read from an input (see `Format`), which may or may not be a
+/// valid transaction.
+#[derive(Debug)]
+pub struct InputRow {
+    /// The line of the row in its input.
+    pub line: u64,
+    /// The row, as it was read (without its line terminator).
+    ///
+    /// May be empty for inputs that aren't text, see `InputRow::raw`.
+    pub raw: String,
+    pub tx: csv::Result<ExternalTx>,
+}
 
-    clients
+impl InputRow {
+    /// The row, as it was read, or otherwise as rendered from the
+    /// transaction.
+    pub fn raw(&self) -> String {
+        match &self.tx {
+            Ok(tx) if self.raw.is_empty() => tx.to_row(),
+            _ => self.raw.clone(),
+        }
+    }
 }
 
-pub fn read_input_file(path: &std::path::Path) -> anyhow::Result<Vec<ExternalTx>> {
+/// Streams the rows from a csv reader.
+///
+/// Each row is only deserialized as the iterator advances, so the
+/// input is never entirely loaded into memory.  
+/// Malformed rows are yielded as `InputRow`s with an error, while errors
+/// that prevent the reading to continue (such as IO errors) are yielded
+/// as errors.
+pub fn read_input<R: std::io::Read>(rdr: R) -> impl Iterator<Item = csv::Result<InputRow>> {
     let mut csv_reader = csv::ReaderBuilder::new();
     csv_reader
-        .trim(csv::Trim::All)
+        // the fields are trimmed after the original row is kept
+        .trim(csv::Trim::None)
         .double_quote(false)
         .quoting(false)
+        // the amount of fields is checked for each row instead
+        .flexible(true)
         // default
         .delimiter(b',')
         .has_headers(true)
-        .flexible(false)
         .terminator(csv::Terminator::CRLF)
         .comment(None);
 
-    let mut reader = csv_reader.from_path(path)?;
-    let mut entries = vec![];
-    for res in reader.deserialize() {
-        let entry: ExternalTx = res?;
-        entries.push(entry);
-    }
-    Ok(entries)
+    let mut reader = csv_reader.from_reader(rdr);
+    let mut headers = None;
+    std::iter::from_fn(move || {
+        if headers.is_none() {
+            headers = match reader.headers() {
+                Ok(headers) => {
+                    let mut headers = headers.clone();
+                    headers.trim();
+                    Some(headers)
+                }
+                Err(e) => return Some(Err(e)),
+            };
+        }
+        let headers = headers.as_ref()?;
+
+        let mut record = csv::ByteRecord::new();
+        let line = reader.position().line();
+        match reader.read_byte_record(&mut record) {
+            Ok(true) => (),
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e)),
+        }
+
+        let line = record.position().map_or(line, csv::Position::line);
+        // without quoting, the original row is its fields joined back
+        let raw =
+            String::from_utf8_lossy(&record.iter().collect::<Vec<_>>().join(&b',')).into_owned();
+        record.trim();
+        let tx = match csv::StringRecord::from_byte_record(record) {
+            Err(e) => Err(csv::Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                e.utf8_error().to_string(),
+            ))),
+            Ok(record) if record.len() != headers.len() => {
+                Err(csv::Error::from(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "found record with {} fields, but the header has {} fields",
+                        record.len(),
+                        headers.len()
+                    ),
+                )))
+            }
+            Ok(record) => record.deserialize(Some(headers)).map(|tx| ExternalTx {
+                row: Some(raw.clone()),
+                ..tx
+            }),
+        };
+        Some(Ok(InputRow { line, raw, tx }))
+    })
+}
+
+/// Streams the rows from a csv file.
+///
+/// The path `-` indicates the stdin.
+pub fn read_input_file(
+    path: &std::path::Path,
+) -> anyhow::Result<impl Iterator<Item = csv::Result<InputRow>>> {
+    Format::Csv.read_input_file(path)
+}
+
+/// Streams the rows from many csv files, in sequence, as if they were a
+/// single input.
+///
+/// Each file must have its own header.  
+/// All files are opened upfront, so a missing file is reported before
+/// any transaction gets processed.
+pub fn read_input_files<P: AsRef<std::path::Path>>(
+    paths: impl IntoIterator<Item = P>,
+) -> anyhow::Result<impl Iterator<Item = csv::Result<InputRow>>> {
+    Format::Csv.read_input_files(paths)
 }
 
 pub fn write_output<W: std::io::Write>(
@@ -78,8 +231,8 @@ pub fn write_output<W: std::io::Write>(
         .terminator(csv::Terminator::CRLF)
         .quote_style(csv::QuoteStyle::Never);
     let mut writer = csv_writer.from_writer(wrt);
-    for entry in clients {
-        writer.serialize(entry)?;
+    for row in OutputRow::all(clients.collect()) {
+        writer.serialize(row)?;
     }
     writer.flush()?;
     Ok(())
@@ -1,4 +1,224 @@
-use tracing::info;
+use payment_engine::{
+    http::HttpServer, policy::Precision, reject::RejectWriter, serve::Server, storage::DiskStorage,
+    Engine, Format, Policy, Reject, Snapshot,
+};
+use std::path::PathBuf;
+use tracing::{error, info};
+
+const USAGE: &str = "Usage: cargo run -- [OPTIONS] transactions.csv [more_transactions.csv ...]
+       cargo run -- [OPTIONS] serve <address:port>
+       cargo run -- [OPTIONS] convert <output.bin> transactions.csv [more_transactions.csv ...]
+Use - to read from stdin
+Use convert to encode the transactions into the binary format
+Use serve to process the csv rows streamed from TCP connections, each row being acked
+
+Options:
+    --rejects <rejects.csv>   Writes the ignored rows into a csv file
+    --workers <N>             Processes the transactions on N threads
+    --policy <file>           Loads the business rules from a toml (or .json) file,
+                              which the other policy options then override
+    --idempotent              Accepts exact resubmissions of stored txs as no-ops
+    --dispute-withdrawals     Allows withdrawals to be disputed, besides deposits
+    --signed-balances         Allows disputes to turn the balances negative (debt)
+    --hold-expiry <K>         Voids the holds of authorize txs after K subsequent txs
+    --credit-limits <file>    Loads the clients' credit limits from a csv file (client, limit)
+    --decimals <N>            Rejects amounts with more than N decimals (the spec uses 4),
+                              and shows the output amounts with exactly N decimals
+    --round                   Rounds the amounts with more decimals instead (requires --decimals)
+    --storage <dir>           Persists the state into a directory, resuming from it
+    --snapshot-every <N>      Snapshots the persisted state every N txs (default 100000)
+    --snapshot-in <file>      Starts from the state of an exported snapshot
+    --snapshot-out <file>     Exports the final state into a snapshot
+    --input-format <format>   Reads the transactions as csv (default), jsonl or bin
+    --output-format <format>  Writes the clients as csv (default) or jsonl
+    --http <address:port>     Also serves a HTTP/JSON API over the same state (serve only)";
+
+struct Args {
+    inputs: Vec<PathBuf>,
+    serve: Option<String>,
+    convert: Option<PathBuf>,
+    http: Option<String>,
+    input_format: Format,
+    output_format: Format,
+    rejects: Option<PathBuf>,
+    workers: usize,
+    policy: Policy,
+    storage: Option<PathBuf>,
+    snapshot_every: usize,
+    snapshot_in: Option<PathBuf>,
+    snapshot_out: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut inputs = vec![];
+        let mut rejects = None;
+        let mut workers = 1;
+        let mut policy_file = None;
+        let mut credit_limits = None;
+        let mut idempotent = false;
+        let mut dispute_withdrawals = false;
+        let mut signed_balances = false;
+        let mut hold_expiry = None;
+        let mut decimals = None;
+        let mut storage = None;
+        let mut snapshot_every = 100_000;
+        let mut snapshot_in = None;
+        let mut snapshot_out = None;
+        let mut http = None;
+        let mut round = false;
+        let mut input_format = Format::default();
+        let mut output_format = Format::default();
+        let mut args = args.skip(1);
+        let mut serve = None;
+        let mut convert = None;
+        while let Some(arg) = args.next() {
+            match arg.trim() {
+                "--rejects" => match args.next() {
+                    Some(path) => rejects = Some(PathBuf::from(path.trim())),
+                    None => anyhow::bail!(USAGE),
+                },
+                "--workers" => match args.next().map(|n| n.trim().parse()) {
+                    Some(Ok(n)) if n > 0 => workers = n,
+                    _ => anyhow::bail!(USAGE),
+                },
+                "--policy" => match args.next() {
+                    Some(path) => policy_file = Some(PathBuf::from(path.trim())),
+                    None => anyhow::bail!(USAGE),
+                },
+                "--credit-limits" => match args.next() {
+                    Some(path) => credit_limits = Some(PathBuf::from(path.trim())),
+                    None => anyhow::bail!(USAGE),
+                },
+                "--idempotent" => idempotent = true,
+                "--dispute-withdrawals" => dispute_withdrawals = true,
+                "--signed-balances" => signed_balances = true,
+                "--hold-expiry" => match args.next().map(|k| k.trim().parse()) {
+                    Some(Ok(k)) => hold_expiry = Some(k),
+                    _ => anyhow::bail!(USAGE),
+                },
+                "--decimals" => match args.next().map(|n| n.trim().parse()) {
+                    Some(Ok(n)) if n <= Precision::MAX_DECIMALS => decimals = Some(n),
+                    _ => anyhow::bail!(USAGE),
+                },
+                "--round" => round = true,
+                "--storage" => match args.next() {
+                    Some(path) => storage = Some(PathBuf::from(path.trim())),
+                    None => anyhow::bail!(USAGE),
+                },
+                "--snapshot-in" => match args.next() {
+                    Some(path) => snapshot_in = Some(PathBuf::from(path.trim())),
+                    None => anyhow::bail!(USAGE),
+                },
+                "--snapshot-out" => match args.next() {
+                    Some(path) => snapshot_out = Some(PathBuf::from(path.trim())),
+                    None => anyhow::bail!(USAGE),
+                },
+                "--input-format" => match args.next() {
+                    Some(format) => input_format = format.trim().parse()?,
+                    None => anyhow::bail!(USAGE),
+                },
+                "--output-format" => match args.next() {
+                    Some(format) => output_format = format.trim().parse()?,
+                    None => anyhow::bail!(USAGE),
+                },
+                "--http" => match args.next() {
+                    Some(addr) => http = Some(addr.trim().to_string()),
+                    None => anyhow::bail!(USAGE),
+                },
+                "--snapshot-every" => match args.next().map(|n| n.trim().parse()) {
+                    Some(Ok(n)) => snapshot_every = n,
+                    _ => anyhow::bail!(USAGE),
+                },
+                "serve" if inputs.is_empty() && serve.is_none() && convert.is_none() => {
+                    match args.next() {
+                        Some(addr) => serve = Some(addr.trim().to_string()),
+                        None => anyhow::bail!(USAGE),
+                    }
+                }
+                "convert" if inputs.is_empty() && serve.is_none() && convert.is_none() => {
+                    match args.next() {
+                        Some(path) => convert = Some(PathBuf::from(path.trim())),
+                        None => anyhow::bail!(USAGE),
+                    }
+                }
+                path => inputs.push(PathBuf::from(path)),
+            }
+        }
+        let mut policy = match policy_file {
+            Some(path) => Policy::load(path)?,
+            None => Policy::default(),
+        };
+        policy.idempotent_replays |= idempotent;
+        policy.withdrawal_disputes |= dispute_withdrawals;
+        policy.signed_balances |= signed_balances;
+        if hold_expiry.is_some() {
+            policy.hold_expiry = hold_expiry;
+        }
+        if let Some(path) = credit_limits {
+            policy.load_credit_limits(path)?;
+        }
+        if let Some(decimals) = decimals {
+            policy.precision = Some(Precision {
+                decimals,
+                round: false,
+            });
+        }
+        match &mut policy.precision {
+            Some(precision) => precision.round |= round,
+            None if round => anyhow::bail!("The rounding requires --decimals"),
+            None => (),
+        }
+        if inputs.is_empty() == serve.is_none() {
+            anyhow::bail!(USAGE);
+        }
+        if serve.is_some() {
+            let rejected = [
+                ("--workers", workers > 1),
+                ("--storage", storage.is_some()),
+                ("--rejects", rejects.is_some()),
+                ("--snapshot-out", snapshot_out.is_some()),
+            ];
+            if let Some((flag, _)) = rejected.iter().find(|(_, used)| *used) {
+                anyhow::bail!("The server can't be used with {}", flag);
+            }
+        }
+        if output_format == Format::Binary {
+            anyhow::bail!("The binary format is only supported for the input");
+        }
+        if convert.is_some() && input_format == Format::Binary {
+            anyhow::bail!("The input is already binary");
+        }
+        if http.is_some() && serve.is_none() {
+            anyhow::bail!("The HTTP API can only be used with serve");
+        }
+        let snapshots = snapshot_in.is_some() || snapshot_out.is_some();
+        if (storage.is_some() || snapshots) && workers > 1 {
+            anyhow::bail!("The storage and snapshots can't be used with many workers");
+        }
+        if policy.fees.is_some() && workers > 1 {
+            anyhow::bail!("The fees can't be used with many workers");
+        }
+        if storage.is_some() && snapshots {
+            anyhow::bail!("The storage can't be used with snapshots");
+        }
+        Ok(Self {
+            inputs,
+            serve,
+            convert,
+            http,
+            input_format,
+            output_format,
+            rejects,
+            workers,
+            policy,
+            storage,
+            snapshot_every,
+            snapshot_in,
+            snapshot_out,
+        })
+    }
+}
 
 fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
@@ -6,15 +226,87 @@ fn main() -> anyhow::Result<()> {
         .init();
     tracing::info!("Execution started");
 
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        anyhow::bail!("Usage: cargo run -- transactions.csv");
+    let args = Args::parse(std::env::args())?;
+
+    if let Some(addr) = &args.serve {
+        let engine = match &args.snapshot_in {
+            Some(path) => Engine::from_snapshot(Snapshot::load(path)?, args.policy.clone())?,
+            None => Engine::new(args.policy.clone()),
+        };
+        let server = Server::bind(addr, engine)?;
+        if let Some(addr) = &args.http {
+            let http = HttpServer::bind(addr, server.engine())?;
+            std::thread::spawn(move || http.run());
+        }
+        server.run();
+        return Ok(());
+    }
+
+    if let Some(path) = &args.convert {
+        let inputs = args.input_format.read_input_files(&args.inputs)?;
+        let output = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let written = payment_engine::binary::convert(inputs, output)?;
+        info!("Converted {} transactions", written);
+        return Ok(());
     }
 
-    let path = std::path::PathBuf::from(args[1].trim());
-    let inputs = payment_engine::read_input_file(&path)?;
-    let clients = payment_engine::run(inputs.into_iter());
-    payment_engine::write_output(clients.values().cloned(), std::io::stdout())?;
+    let mut rejects = match &args.rejects {
+        // a resumed execution keeps the rejects of the skipped rows
+        Some(path) if args.storage.is_some() => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            if file.metadata()?.len() > 0 {
+                Some(RejectWriter::appending(file))
+            } else {
+                Some(RejectWriter::new(file))
+            }
+        }
+        Some(path) => Some(RejectWriter::new(std::fs::File::create(path)?)),
+        None => None,
+    };
+
+    let inputs = args.input_format.read_input_files(&args.inputs)?;
+    let on_reject = |reject: Reject| {
+        error!("Line {}: {}", reject.line, reject.reason);
+        match &mut rejects {
+            Some(rejects) => rejects.write(&reject),
+            None => Ok(()),
+        }
+    };
+    let clients = if args.workers > 1 {
+        payment_engine::parallel::run_rows(inputs, args.workers, &args.policy, on_reject)?
+    } else if let Some(dir) = &args.storage {
+        let mut storage = DiskStorage::open(dir, args.snapshot_every)?;
+        payment_engine::run_rows_with_storage(inputs, &args.policy, &mut storage, on_reject)?
+    } else if args.snapshot_in.is_some() || args.snapshot_out.is_some() {
+        let snapshot = match &args.snapshot_in {
+            Some(path) => Snapshot::load(path)?,
+            None => Snapshot::default(),
+        };
+        let snapshot =
+            payment_engine::run_rows_from_snapshot(snapshot, inputs, &args.policy, on_reject)?;
+        if let Some(path) = &args.snapshot_out {
+            snapshot.save(path)?;
+        }
+        snapshot
+            .clients
+            .into_iter()
+            .map(|client| (client.id.clone(), client))
+            .collect()
+    } else {
+        payment_engine::run_rows(inputs, &args.policy, on_reject)?
+    };
+    if let Some(rejects) = &mut rejects {
+        rejects.flush()?;
+    }
+    args.output_format.write_output(
+        clients
+            .values()
+            .map(|client| args.policy.render(client.clone())),
+        std::io::stdout(),
+    )?;
 
     info!("Execution finished");
     Ok(())
@@ -0,0 +1,347 @@
+//! Processing of transactions on many threads.
+//!
+//! Since clients never interact with one another, each worker thread
+//! owns a shard of the clients and of their past transactions, and each
+//! transaction is routed into a worker based on its client id.  
+//! A client is always routed into the same worker, so the order of
+//! each client's transactions is preserved, and the resulting clients
+//! are the same as the ones from the serial processing.
+//!
+//! A tx id reused by another client is checked by the dispatcher, which
+//! asks the worker of the client that first used it whether that tx was
+//! stored.
+//!
+//! Note: a dispute that indicates a tx of another client may be
+//! reported as a not-found tx (instead of a tx of another client), as
+//! that tx may be stored in another shard.  
+//! Transfers are rejected, as their clients may be owned by different
+//! workers.  
+//! The timestamp of a tx is also sent into every worker, as are the holds
+//! that are due by the end, so the holds expire the same as in the
+//! serial processing.  
+//! The fees are not supported, as the house client would be owned by a
+//! single worker.
+
+use crate::{
+    client::ClTxError,
+    tx::{InternalTxId, TxError, TxId},
+    types::ClientId,
+    Clients, Engine, ExternalTx, InputRow, Policy, Reject, TxType,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::mpsc,
+    thread,
+};
+use tracing::error;
+
+/// Amount of transactions that are sent at once into a worker.
+const BATCH_SIZE: usize = 1024;
+
+/// Amount of batches that may be waiting for each worker.
+const QUEUED_BATCHES: usize = 16;
+
+/// Same as `crate::run`, but the processing is spread into `workers`
+/// threads.
+pub fn run(inputs: impl Iterator<Item = ExternalTx>, workers: usize) -> Clients {
+    match try_run(inputs.map(Ok::<_, std::convert::Infallible>), workers) {
+        Ok(clients) => clients,
+        Err(never) => match never {},
+    }
+}
+
+/// Same as `crate::try_run`, but the processing is spread into `workers`
+/// threads.
+pub fn try_run<E>(
+    inputs: impl Iterator<Item = Result<ExternalTx, E>>,
+    workers: usize,
+) -> Result<Clients, E> {
+    let mut pool = Pool::new(workers, &Policy::default());
+    for cltx in inputs {
+        let cltx = match cltx {
+            Ok(cltx) => cltx,
+            Err(e) => {
+                pool.finish(|(), _e| ());
+                return Err(e);
+            }
+        };
+        pool.dispatch(cltx, ());
+        pool.drain_rejects(|(), e| error!("{}", e));
+    }
+    Ok(pool.finish(|(), e| error!("{}", e)))
+}
+
+/// Same as `crate::run_rows`, but the processing is spread into
+/// `workers` threads.
+///
+/// The ignored transactions are reported into `on_reject` in the order
+/// of each client's transactions, but not necessarily in the order of
+/// the rows.
+///
+/// A policy with fees is rejected (see the module docs).
+pub fn run_rows(
+    rows: impl Iterator<Item = csv::Result<InputRow>>,
+    workers: usize,
+    policy: &Policy,
+    mut on_reject: impl FnMut(Reject) -> anyhow::Result<()>,
+) -> anyhow::Result<Clients> {
+    if policy.fees.is_some() {
+        anyhow::bail!("The fees can't be used with many workers");
+    }
+    let mut pool = Pool::new(workers, policy);
+    let mut result = Ok(());
+    for row in rows {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                result = Err(e.into());
+                break;
+            }
+        };
+        result = match &row.tx {
+            Ok(cltx) => {
+                let cltx = cltx.clone();
+                pool.dispatch(cltx, row);
+                Ok(())
+            }
+            Err(e) => on_reject(Reject::from_malformed(&row, pool.skip(), e)),
+        };
+        if result.is_ok() {
+            pool.drain_rejects(|row, e| {
+                if result.is_ok() {
+                    result = on_reject(Reject::from_tx_error(&row, &e));
+                }
+            });
+        }
+        if result.is_err() {
+            break;
+        }
+    }
+    let clients = pool.finish(|row, e| {
+        if result.is_ok() {
+            result = on_reject(Reject::from_tx_error(&row, &e));
+        }
+    });
+    result.map(|()| clients)
+}
+
+/// A transaction to be processed by a worker, and some context that is
+/// returned back in case the transaction is ignored.
+struct Job<C> {
+    cltx: ExternalTx,
+    internal_txid: InternalTxId,
+    context: C,
+}
+
+/// What a worker is sent, in order.
+enum Work<C> {
+    Job(Job<C>),
+    /// Asks whether the tx id is stored by the worker.
+    IsStored(TxId, mpsc::Sender<bool>),
+    /// Expires the holds that are due by a tx of another worker.
+    ExpireHolds(InternalTxId, Option<u64>),
+}
+
+/// The worker threads, each owning their own `Engine`.
+struct Pool<C> {
+    senders: Vec<mpsc::SyncSender<Vec<Work<C>>>>,
+    batches: Vec<Vec<Work<C>>>,
+    handles: Vec<thread::JoinHandle<Clients>>,
+    rejects: mpsc::Receiver<(C, TxError)>,
+    reject_sender: mpsc::Sender<(C, TxError)>,
+    internal_txid: InternalTxId,
+    /// The client that last dispatched each tx id that gets stored.
+    txids: HashMap<TxId, ClientId>,
+    /// The last dispatched tx.
+    last: Option<InternalTxId>,
+}
+
+impl<C: Send + 'static> Pool<C> {
+    fn new(workers: usize, policy: &Policy) -> Self {
+        let workers = workers.max(1);
+        let (reject_sender, rejects) = mpsc::channel();
+        let mut senders = vec![];
+        let mut handles = vec![];
+        for _ in 0..workers {
+            let (sender, receiver) = mpsc::sync_channel::<Vec<Work<C>>>(QUEUED_BATCHES);
+            let reject_sender = reject_sender.clone();
+            let policy = policy.clone();
+            let handle = thread::spawn(move || {
+                let mut engine = Engine::new(policy);
+                for batch in receiver {
+                    for work in batch {
+                        let job = match work {
+                            Work::Job(job) => job,
+                            Work::IsStored(txid, answer) => {
+                                let _ = answer.send(engine.tx(&txid).is_some());
+                                continue;
+                            }
+                            Work::ExpireHolds(internal_txid, timestamp) => {
+                                engine.expire_holds(&internal_txid, timestamp);
+                                continue;
+                            }
+                        };
+                        let processed = if job.cltx.ty == TxType::Transfer {
+                            let e = ClTxError::TransferUnsupportedError;
+                            Err(job.cltx.client_error(e, job.internal_txid))
+                        } else {
+                            engine.process_as(&job.cltx, job.internal_txid)
+                        };
+                        if let Err(e) = processed {
+                            // the receiver only stops when all rejects
+                            // are unwanted
+                            let _ = reject_sender.send((job.context, e));
+                        }
+                    }
+                }
+                engine.finish()
+            });
+            senders.push(sender);
+            handles.push(handle);
+        }
+        Self {
+            senders,
+            batches: (0..workers)
+                .map(|_| Vec::with_capacity(BATCH_SIZE))
+                .collect(),
+            handles,
+            rejects,
+            reject_sender,
+            internal_txid: InternalTxId::default(),
+            txids: HashMap::new(),
+            last: None,
+        }
+    }
+
+    /// Steps the `InternalTxId` without processing any transaction,
+    /// returning the skipped id.
+    fn skip(&mut self) -> InternalTxId {
+        let internal_txid = self.internal_txid.clone();
+        self.internal_txid.step();
+        internal_txid
+    }
+
+    /// The worker that owns the client.
+    fn worker(&self, client: &ClientId) -> usize {
+        let mut hasher = DefaultHasher::new();
+        client.hash(&mut hasher);
+        (hasher.finish() % self.senders.len() as u64) as usize
+    }
+
+    /// Routes the transaction into its client's worker.
+    ///
+    /// A tx id that was stored by another client is rejected here, as
+    /// the worker only sees its own clients' txs.
+    fn dispatch(&mut self, cltx: ExternalTx, context: C) {
+        let worker = self.worker(&cltx.client);
+        let internal_txid = self.skip();
+        self.last = Some(internal_txid.clone());
+
+        // a timestamp also expires the holds of the other workers' clients,
+        // even if its tx gets rejected
+        if cltx.timestamp.is_some() {
+            for shard in 0..self.senders.len() {
+                let expire = Work::ExpireHolds(internal_txid.clone(), cltx.timestamp);
+                self.batches[shard].push(expire);
+                if self.batches[shard].len() >= BATCH_SIZE {
+                    self.flush(shard);
+                }
+            }
+        }
+
+        if let TxType::Deposit | TxType::Withdrawal | TxType::Authorize = cltx.ty {
+            match self.txids.get(&cltx.txid).cloned() {
+                Some(owner) if owner != cltx.client && self.is_stored(&owner, &cltx.txid) => {
+                    let e = ClTxError::DuplicateTxIdError(cltx.txid.clone());
+                    let e = cltx.client_error(e, internal_txid);
+                    // the receiver is owned by the pool itself
+                    let _ = self.reject_sender.send((context, e));
+                    return;
+                }
+                Some(owner) if owner == cltx.client => (),
+                _ => {
+                    self.txids.insert(cltx.txid.clone(), cltx.client.clone());
+                }
+            }
+        }
+
+        self.batches[worker].push(Work::Job(Job {
+            cltx,
+            internal_txid,
+            context,
+        }));
+        if self.batches[worker].len() >= BATCH_SIZE {
+            self.flush(worker);
+        }
+    }
+
+    /// Whether the tx id is stored by the worker of the `client`, once
+    /// the txs that were already dispatched into it get processed.
+    fn is_stored(&mut self, client: &ClientId, txid: &TxId) -> bool {
+        let worker = self.worker(client);
+        let (answer, answered) = mpsc::channel();
+        self.batches[worker].push(Work::IsStored(txid.clone(), answer));
+        self.flush(worker);
+        match answered.recv() {
+            Ok(stored) => stored,
+            Err(_) => {
+                // the worker has stopped, which only happens on a panic
+                self.join();
+                unreachable!("a worker has stopped without panicking");
+            }
+        }
+    }
+
+    fn flush(&mut self, worker: usize) {
+        let batch = std::mem::replace(&mut self.batches[worker], Vec::with_capacity(BATCH_SIZE));
+        if self.senders[worker].send(batch).is_err() {
+            // the worker has stopped, which only happens on a panic
+            self.join();
+            unreachable!("a worker has stopped without panicking");
+        }
+    }
+
+    /// Reports the currently ignored transactions.
+    fn drain_rejects(&mut self, mut on_reject: impl FnMut(C, TxError)) {
+        for (context, e) in self.rejects.try_iter() {
+            on_reject(context, e);
+        }
+    }
+
+    /// Processes the remaining transactions, reports the remaining
+    /// ignored transactions and merges the clients from all workers.
+    fn finish(mut self, mut on_reject: impl FnMut(C, TxError)) -> Clients {
+        if let Some(internal_txid) = self.last.take() {
+            for batch in &mut self.batches {
+                batch.push(Work::ExpireHolds(internal_txid.clone(), None));
+            }
+        }
+        for worker in 0..self.senders.len() {
+            if !self.batches[worker].is_empty() {
+                self.flush(worker);
+            }
+        }
+        let clients = self.join();
+        for (context, e) in self.rejects.try_iter() {
+            on_reject(context, e);
+        }
+        clients
+    }
+
+    /// Waits for all workers to finish.
+    ///
+    /// Resumes the panic of a worker, if any.
+    fn join(&mut self) -> Clients {
+        // closes the workers' channels
+        self.senders.clear();
+        let mut clients = Clients::new();
+        for handle in self.handles.drain(..) {
+            match handle.join() {
+                Ok(shard) => clients.extend(shard),
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+        clients
+    }
+}
@@ -0,0 +1,383 @@
+use crate::{
+    client::{AccountStatus, ClTxError},
+    types::{Amount, ClientId},
+    Client, TxType,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// Business rules that may differ between engine executions.
+///
+/// May be loaded from a toml or json file (see `Policy::load`), where
+/// every field is optional, such as:
+///
+/// ```toml
+/// idempotent_replays = true
+/// withdrawal_disputes = true
+/// signed_balances = true
+/// hold_expiry = 100
+///
+/// [precision]
+/// decimals = 4
+/// round = true
+///
+/// [fees]
+/// house = 0
+/// withdrawal = { flat = "0.5" }
+/// chargeback = { tiered = [
+///     { from = "0", fee = { flat = "15" } },
+///     { from = "1000", fee = { percentage = "1.5" } },
+/// ] }
+///
+/// [[fees.clients]]
+/// client = 7
+/// withdrawal = { percentage = "1" }
+///
+/// [[credit_limits]]
+/// client = 7
+/// limit = "500"
+///
+/// [statuses]
+/// frozen = ["dispute", "resolve", "chargeback", "unlock", "close"]
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Whether a deposit, withdrawal, transfer or authorize that is an
+    /// exact resubmission of an already stored one is silently accepted
+    /// as a no-op (see `Tx::is_identical`).
+    ///
+    /// Otherwise, and for any other tx that reuses a stored tx id, the
+    /// tx is rejected.
+    pub idempotent_replays: bool,
+    /// Whether withdrawals may also be disputed, besides deposits.
+    ///
+    /// A disputed withdrawal has its amount returned into `held` (and
+    /// into `total`), pending resolution. A resolve then re-debits it
+    /// from `held` and `total`, while a chargeback credits it into
+    /// `available`, and locks the client.
+    pub withdrawal_disputes: bool,
+    /// Whether a client locked by a chargeback still accepts deposits.
+    ///
+    /// See `AccountStatus::accepts` for the other transactions.
+    pub locked_deposits: bool,
+    /// The tx types that a client in each status accepts, replacing the
+    /// ones of `AccountStatus::accepts` (and `locked_deposits`) for the
+    /// statuses that are given.
+    pub statuses: Statuses,
+    /// Whether a dispute may turn `available` negative, instead of being
+    /// rejected for insufficient funds.
+    ///
+    /// Its chargeback may then also turn `total` negative, which is the
+    /// client's debt (see `Client::debt`).
+    pub signed_balances: bool,
+    /// The amount of subsequent txs after which the hold of an authorize
+    /// expires, if it wasn't captured or voided by then.
+    ///
+    /// The expired hold is voided before the next tx is processed.
+    pub hold_expiry: Option<u32>,
+    /// The precision of the amounts, if limited.
+    pub precision: Option<Precision>,
+    /// The fees charged on withdrawals and chargebacks, if any.
+    pub fees: Option<Fees>,
+    /// The credit limits that clients start with, see
+    /// `Client::credit_limit`.
+    pub credit_limits: Vec<CreditLimit>,
+}
+
+impl Policy {
+    /// Loads the policy from a file, in json if it has the `.json`
+    /// extension, and otherwise in toml.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        let policy = match path.extension() {
+            Some(ext) if ext == "json" => Self::from_json(&content),
+            _ => Self::from_toml(&content),
+        };
+        policy.map_err(|e| anyhow::anyhow!("Invalid policy file {:?}. {}", path, e))
+    }
+
+    pub fn from_toml(content: &str) -> anyhow::Result<Self> {
+        let policy: Self = toml::from_str(content)?;
+        policy.check()?;
+        Ok(policy)
+    }
+
+    pub fn from_json(content: &str) -> anyhow::Result<Self> {
+        let policy: Self = serde_json::from_str(content)?;
+        policy.check()?;
+        Ok(policy)
+    }
+
+    /// Adds the credit limits of a csv file, with the `client` and
+    /// `limit` columns, which replace the previous ones of the same
+    /// clients.
+    pub fn load_credit_limits(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        for limit in reader.deserialize() {
+            let limit =
+                limit.map_err(|e| anyhow::anyhow!("Invalid credit limits {:?}. {}", path, e))?;
+            self.credit_limits.push(limit);
+        }
+        self.check()
+    }
+
+    /// Checks the values that are valid for the format, but not for the
+    /// engine.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if let Some(precision) = &self.precision {
+            if precision.decimals > Precision::MAX_DECIMALS {
+                anyhow::bail!(
+                    "The precision has {} decimals, but at most {} are supported",
+                    precision.decimals,
+                    Precision::MAX_DECIMALS
+                );
+            }
+        }
+        if let Some(fees) = &self.fees {
+            fees.check()?;
+        }
+        for limit in &self.credit_limits {
+            if limit.limit.is_negative() {
+                anyhow::bail!(
+                    "The credit limit {:?} of client {:?} is negative",
+                    limit.limit,
+                    limit.client
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// A new client, with its credit limit (the last one given), if any.
+    pub fn new_client(&self, id: &ClientId) -> Client {
+        let mut client = Client::new(id);
+        if let Some(limit) = self.credit_limits.iter().rev().find(|l| &l.client == id) {
+            client.credit_limit = limit.limit.clone();
+        }
+        client
+    }
+
+    /// Checks that a client in this status accepts a transaction of
+    /// this type.
+    pub fn check_status(&self, status: &AccountStatus, ty: &TxType) -> Result<(), ClTxError> {
+        let accepted = match (self.statuses.get(*status), status, ty) {
+            (Some(accepted), _, _) => accepted.contains(ty),
+            (None, AccountStatus::Locked, TxType::Deposit) if self.locked_deposits => true,
+            (None, _, _) => status.accepts(ty),
+        };
+        if accepted {
+            Ok(())
+        } else {
+            Err(status.error())
+        }
+    }
+
+    /// Whether a stored tx of this type may be disputed (and then
+    /// charged back).
+    pub fn is_disputable(&self, ty: &TxType) -> bool {
+        match ty {
+            TxType::Deposit | TxType::Transfer => true,
+            TxType::Withdrawal => self.withdrawal_disputes,
+            _ => false,
+        }
+    }
+
+    /// The fee that a client is charged on a tx of this type and amount,
+    /// if any.
+    ///
+    /// The house client is never charged, and the fee is rounded into the
+    /// `precision`.
+    pub fn fee(
+        &self,
+        client: &ClientId,
+        ty: &TxType,
+        amount: &Amount,
+    ) -> Result<Option<Amount>, ClTxError> {
+        let fees = match &self.fees {
+            Some(fees) if &fees.house != client => fees,
+            _ => return Ok(None),
+        };
+        let (withdrawal, chargeback) = match fees.clients.iter().find(|c| &c.client == client) {
+            Some(schedule) => (&schedule.withdrawal, &schedule.chargeback),
+            None => (&fees.withdrawal, &fees.chargeback),
+        };
+        let fee = match ty {
+            TxType::Withdrawal => withdrawal,
+            TxType::Chargeback => chargeback,
+            _ => &None,
+        };
+        let fee = match fee {
+            Some(fee) => fee.on(amount)?,
+            None => return Ok(None),
+        };
+        let fee = match &self.precision {
+            Some(precision) => fee.round(precision.decimals),
+            None => fee,
+        };
+        Ok(Some(fee).filter(|fee| !fee.is_zero()))
+    }
+
+    /// The client as it should be shown in the outputs.
+    pub fn render(&self, mut client: Client) -> Client {
+        if let Some(precision) = &self.precision {
+            client.rescale(precision.decimals);
+        }
+        client
+    }
+}
+
+/// The tx types accepted by a client in each status, for the statuses
+/// that are given (see `Policy::statuses`).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Statuses {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active: Option<Vec<TxType>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked: Option<Vec<TxType>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frozen: Option<Vec<TxType>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed: Option<Vec<TxType>>,
+}
+
+impl Statuses {
+    pub fn get(&self, status: AccountStatus) -> Option<&Vec<TxType>> {
+        match status {
+            AccountStatus::Active => self.active.as_ref(),
+            AccountStatus::Locked => self.locked.as_ref(),
+            AccountStatus::Frozen => self.frozen.as_ref(),
+            AccountStatus::Closed => self.closed.as_ref(),
+        }
+    }
+}
+
+/// The amount of decimals that the amounts may have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Precision {
+    /// The maximum amount of decimals of the input amounts, which is
+    /// also the amount of decimals shown in the outputs.
+    pub decimals: u32,
+    /// Whether an input amount with more decimals is rounded (half to
+    /// even), instead of having its tx rejected.
+    #[serde(default)]
+    pub round: bool,
+}
+
+impl Precision {
+    /// The maximum amount of decimals that an amount supports.
+    pub const MAX_DECIMALS: u32 = 28;
+}
+
+/// The fees charged on withdrawals and chargebacks, which are credited
+/// into the `house` client.
+///
+/// The fee is debited from the client together with its tx, so a
+/// withdrawal is rejected if the client can't also afford the fee. A
+/// chargeback is never refused, and its fee is capped at what the client
+/// can afford, unless `Policy::signed_balances` lets it leave a debt.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fees {
+    /// The client that receives the fees, which is never charged.
+    pub house: ClientId,
+    #[serde(default)]
+    pub withdrawal: Option<Fee>,
+    #[serde(default)]
+    pub chargeback: Option<Fee>,
+    /// The fees of specific clients, which replace the ones above.
+    #[serde(default)]
+    pub clients: Vec<ClientFees>,
+}
+
+impl Fees {
+    fn check(&self) -> anyhow::Result<()> {
+        let fees = self
+            .clients
+            .iter()
+            .flat_map(|client| vec![&client.withdrawal, &client.chargeback])
+            .chain(vec![&self.withdrawal, &self.chargeback]);
+        for fee in fees.flatten() {
+            fee.check()?;
+        }
+        Ok(())
+    }
+}
+
+/// The fees of a specific client.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientFees {
+    pub client: ClientId,
+    #[serde(default)]
+    pub withdrawal: Option<Fee>,
+    #[serde(default)]
+    pub chargeback: Option<Fee>,
+}
+
+/// The fee on the amount of a tx (or, for a chargeback, of the reversed
+/// tx).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Fee {
+    /// A fixed amount.
+    Flat(Amount),
+    /// A percentage of the amount.
+    Percentage(Amount),
+    /// The fee of the tier with the greatest `from` that is not above the
+    /// amount, if any.
+    Tiered(Vec<Tier>),
+}
+
+impl Fee {
+    /// The fee on the `amount`.
+    pub fn on(&self, amount: &Amount) -> Result<Amount, ClTxError> {
+        match self {
+            Fee::Flat(fee) => Ok(fee.clone()),
+            Fee::Percentage(percentage) => amount
+                .percentage(percentage)
+                .ok_or_else(|| ClTxError::AmountOverflowError(amount.clone(), percentage.clone())),
+            Fee::Tiered(tiers) => match tiers
+                .iter()
+                .filter(|tier| &tier.from <= amount)
+                .max_by(|a, b| a.from.cmp(&b.from))
+            {
+                Some(tier) => tier.fee.on(amount),
+                None => Ok(Amount::default()),
+            },
+        }
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        match self {
+            Fee::Flat(amount) | Fee::Percentage(amount) if amount.is_negative() => {
+                anyhow::bail!("The fee {:?} is negative", amount)
+            }
+            Fee::Flat(_) | Fee::Percentage(_) => Ok(()),
+            Fee::Tiered(tiers) => tiers.iter().try_for_each(|tier| tier.fee.check()),
+        }
+    }
+}
+
+/// A tier of a `Fee::Tiered`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tier {
+    /// The minimum amount of the tier.
+    pub from: Amount,
+    pub fee: Fee,
+}
+
+/// The credit limit that a client starts with.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreditLimit {
+    pub client: ClientId,
+    pub limit: Amount,
+}
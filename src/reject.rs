@@ -0,0 +1,84 @@
+use crate::{
+    tx::{InternalTxId, TxError},
+    InputRow,
+};
+use serde::Serialize;
+
+/// A row that was ignored, either because it couldn't be read as a
+/// transaction, or because the transaction itself was rejected.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Reject {
+    /// The line of the row in its input.
+    pub line: u64,
+    #[serde(rename = "internal_tx")]
+    pub internal_txid: InternalTxId,
+    /// A stable code, such as `E_INSUFFICIENT_FUNDS`.
+    pub code: &'static str,
+    /// A human-readable description of the error.
+    pub reason: String,
+    /// The (trimmed) fields of the row, as they were read.
+    pub row: String,
+}
+
+impl Reject {
+    /// Code for rows that couldn't be read as a transaction.
+    pub const MALFORMED_ROW: &'static str = "E_MALFORMED_ROW";
+
+    pub fn from_tx_error(row: &InputRow, error: &TxError) -> Self {
+        Self {
+            line: row.line,
+            internal_txid: error.internal_txid().clone(),
+            code: error.code(),
+            reason: error.to_string(),
+            row: row.raw(),
+        }
+    }
+
+    pub fn from_malformed(row: &InputRow, internal_txid: InternalTxId, error: &csv::Error) -> Self {
+        Self {
+            line: row.line,
+            internal_txid,
+            code: Self::MALFORMED_ROW,
+            reason: error.to_string(),
+            row: row.raw(),
+        }
+    }
+}
+
+/// Writes `Reject`s in csv format.
+pub struct RejectWriter<W: std::io::Write>(csv::Writer<W>);
+
+impl<W: std::io::Write> RejectWriter<W> {
+    pub fn new(wrt: W) -> Self {
+        Self::with_headers(wrt, true)
+    }
+
+    /// Same as `new`, but without the header, for appending into a
+    /// file that already has one.
+    pub fn appending(wrt: W) -> Self {
+        Self::with_headers(wrt, false)
+    }
+
+    fn with_headers(wrt: W, has_headers: bool) -> Self {
+        let mut csv_writer = csv::WriterBuilder::new();
+        csv_writer
+            // the rows and reasons may contain commas
+            .quote_style(csv::QuoteStyle::Necessary)
+            // default
+            .delimiter(b',')
+            .has_headers(has_headers)
+            .flexible(false)
+            .terminator(csv::Terminator::CRLF);
+        Self(csv_writer.from_writer(wrt))
+    }
+
+    pub fn write(&mut self, reject: &Reject) -> anyhow::Result<()> {
+        self.0.serialize(reject)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
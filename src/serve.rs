@@ -0,0 +1,183 @@
+//! Processing of transactions streamed from TCP connections.
+//!
+//! Each connection streams csv rows, in the same format as an input
+//! file (including the header), and each row is answered with an `Ack`
+//! as soon as it's processed. All connections share the same `Engine`,
+//! so the transactions of a client may come from any connection.
+//!
+//! The acks are written while the rows are read, so a client that
+//! sends many rows should also be reading the acks, otherwise both
+//! sides may end up waiting for one another.
+
+use crate::{tx::InternalTxId, Engine, InputRow, Outcome, Reject};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+};
+use tracing::{error, info, warn};
+
+/// How a row was handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Accepted,
+    /// Accepted as an exact resubmission of a stored transaction.
+    Replayed,
+    Rejected,
+}
+
+impl From<&Outcome> for Status {
+    fn from(outcome: &Outcome) -> Self {
+        match outcome {
+            Outcome::Applied(_) => Status::Accepted,
+            Outcome::Replayed(_) => Status::Replayed,
+        }
+    }
+}
+
+/// The answer for a row.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ack {
+    /// The line of the row in its connection.
+    pub line: u64,
+    #[serde(rename = "internal_tx")]
+    pub internal_txid: InternalTxId,
+    pub status: Status,
+    /// The error code of a rejected row, otherwise empty.
+    pub code: String,
+    /// The error description of a rejected row, otherwise empty.
+    pub reason: String,
+}
+
+impl Ack {
+    pub fn from_outcome(row: &InputRow, outcome: &Outcome) -> Self {
+        Self {
+            line: row.line,
+            internal_txid: outcome.internal_txid().clone(),
+            status: Status::from(outcome),
+            code: String::new(),
+            reason: String::new(),
+        }
+    }
+
+    pub fn from_reject(reject: Reject) -> Self {
+        Self {
+            line: reject.line,
+            internal_txid: reject.internal_txid,
+            status: Status::Rejected,
+            code: reject.code.to_string(),
+            reason: reject.reason,
+        }
+    }
+}
+
+/// Accepts connections, processing their rows into a shared `Engine`.
+pub struct Server {
+    listener: TcpListener,
+    engine: Arc<Mutex<Engine>>,
+}
+
+impl Server {
+    pub fn bind(addr: impl ToSocketAddrs, engine: Engine) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            engine: Arc::new(Mutex::new(engine)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// The shared engine, which may be inspected while the server runs.
+    pub fn engine(&self) -> Arc<Mutex<Engine>> {
+        Arc::clone(&self.engine)
+    }
+
+    /// Accepts connections forever, each handled on its own thread.
+    pub fn run(self) {
+        info!("Listening on {:?}", self.listener.local_addr());
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    // such as when running out of file descriptors
+                    warn!("Failed to accept a connection. {}", e);
+                    continue;
+                }
+            };
+            let engine = Arc::clone(&self.engine);
+            thread::spawn(move || {
+                let peer = stream.peer_addr();
+                if let Err(e) = handle(stream, &engine) {
+                    warn!("Connection {:?} failed. {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+/// Processes the rows of a connection, answering each with an `Ack`.
+fn handle(stream: TcpStream, engine: &Mutex<Engine>) -> anyhow::Result<()> {
+    let mut acks = AckWriter::new(io::BufWriter::new(stream.try_clone()?));
+    for row in crate::read_input(stream) {
+        let row = row?;
+        let ack = process(&row, engine);
+        if ack.status == Status::Rejected {
+            error!("Line {}: {}", ack.line, ack.reason);
+        }
+        acks.write(&ack)?;
+        acks.flush()?;
+    }
+    Ok(())
+}
+
+fn process(row: &InputRow, engine: &Mutex<Engine>) -> Ack {
+    let mut engine = engine.lock().expect("Another connection panicked");
+    match &row.tx {
+        Ok(cltx) => match engine.process(cltx) {
+            Ok(outcome) => Ack::from_outcome(row, &outcome),
+            Err(e) => Ack::from_reject(Reject::from_tx_error(row, &e)),
+        },
+        Err(e) => Ack::from_reject(Reject::from_malformed(row, engine.skip(), e)),
+    }
+}
+
+/// Writes `Ack`s in csv format.
+pub struct AckWriter<W: io::Write>(csv::Writer<W>);
+
+impl<W: io::Write> AckWriter<W> {
+    pub fn new(wrt: W) -> Self {
+        let mut csv_writer = csv::WriterBuilder::new();
+        csv_writer
+            // the reasons may contain commas
+            .quote_style(csv::QuoteStyle::Necessary)
+            // default
+            .delimiter(b',')
+            .has_headers(true)
+            .flexible(false)
+            .terminator(csv::Terminator::CRLF);
+        Self(csv_writer.from_writer(wrt))
+    }
+
+    pub fn write(&mut self, ack: &Ack) -> anyhow::Result<()> {
+        self.0.serialize(ack)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads the `Ack`s answered by a server.
+pub fn read_acks<R: io::Read>(rdr: R) -> impl Iterator<Item = csv::Result<Ack>> {
+    csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(rdr)
+        .into_deserialize()
+}
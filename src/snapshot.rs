@@ -0,0 +1,88 @@
+use crate::{
+    tx::{InternalTxId, Tx},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// The complete state of the engine, which can be persisted and later
+/// restored.
+///
+/// This includes the clients, the stored transactions (including their
+/// dispute status) and the `InternalTxId` counter.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The version of the snapshot format.
+    pub version: u32,
+    /// The id of the next incoming transaction.
+    pub next_internal_txid: InternalTxId,
+    pub clients: Vec<Client>,
+    pub txs: Vec<Tx>,
+}
+
+/// An empty state.
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self {
+            version: Self::VERSION,
+            next_internal_txid: InternalTxId::default(),
+            clients: vec![],
+            txs: vec![],
+        }
+    }
+}
+
+impl Snapshot {
+    /// The current version of the snapshot format.
+    pub const VERSION: u32 = 2;
+
+    /// Reads a snapshot in json format.
+    pub fn read<R: io::Read>(rdr: R) -> anyhow::Result<Self> {
+        let snapshot: Self = serde_json::from_reader(io::BufReader::new(rdr))?;
+        snapshot.check_version()?;
+        Ok(snapshot)
+    }
+
+    /// Writes the snapshot in json format.
+    pub fn write<W: io::Write>(&self, wrt: W) -> anyhow::Result<()> {
+        let mut wrt = io::BufWriter::new(wrt);
+        serde_json::to_writer(&mut wrt, self)?;
+        wrt.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::read(fs::File::open(path)?)
+    }
+
+    /// Writes the snapshot into a file.
+    ///
+    /// The snapshot is first completely written (and synced) into a
+    /// temporary file, which then replaces the destination file, so the
+    /// destination always holds a complete snapshot.
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let file = fs::File::create(&tmp)?;
+        self.write(&file)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn check_version(&self) -> anyhow::Result<()> {
+        if self.version != Self::VERSION {
+            anyhow::bail!(
+                "Unsupported snapshot version {}, expected {}",
+                self.version,
+                Self::VERSION
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+//! Persistence of the engine's state.
+//!
+//! After each transaction is fully applied, it gets appended into a
+//! write-ahead log (WAL), as does the position of each rejected row (and
+//! the whole transaction, if it still changed the state), and
+//! periodically the whole state is written as a `Snapshot`, after which
+//! the log is restarted.
+//! When the engine is restarted, the last snapshot is loaded and the
+//! logged transactions are processed again on top of it.
+
+use crate::{tx::InternalTxId, ExternalTx, Snapshot};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+/// A transaction that was applied, or a row that was rejected.
+///
+/// A rejected row only keeps its transaction if it still changed the
+/// state, such as by expiring some holds.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalEntry {
+    pub internal_txid: InternalTxId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx: Option<ExternalTx>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rejected: bool,
+}
+
+impl WalEntry {
+    pub fn new(internal_txid: InternalTxId, tx: ExternalTx) -> Self {
+        Self {
+            internal_txid,
+            tx: Some(tx),
+            rejected: false,
+        }
+    }
+
+    pub fn rejected(internal_txid: InternalTxId, tx: Option<ExternalTx>) -> Self {
+        Self {
+            internal_txid,
+            tx,
+            rejected: true,
+        }
+    }
+}
+
+/// Where the engine's state is persisted.
+pub trait Storage {
+    /// Loads the last snapshot, if any, and the entries that were
+    /// appended after it.
+    fn recover(&mut self) -> anyhow::Result<(Option<Snapshot>, Vec<WalEntry>)>;
+
+    /// Logs a transaction that was applied, or a row that was rejected.
+    fn append(&mut self, entry: &WalEntry) -> anyhow::Result<()>;
+
+    /// Whether a snapshot should be taken, which is checked after each
+    /// `append`.
+    fn wants_snapshot(&self) -> bool;
+
+    /// Persists the whole state, after which the previous entries are no
+    /// longer needed.
+    fn snapshot(&mut self, snapshot: &Snapshot) -> anyhow::Result<()>;
+
+    /// Makes sure that everything that was written is persisted.
+    fn sync(&mut self) -> anyhow::Result<()>;
+}
+
+/// Doesn't persist anything.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoStorage;
+
+impl Storage for NoStorage {
+    fn recover(&mut self) -> anyhow::Result<(Option<Snapshot>, Vec<WalEntry>)> {
+        Ok((None, vec![]))
+    }
+    fn append(&mut self, _entry: &WalEntry) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn wants_snapshot(&self) -> bool {
+        false
+    }
+    fn snapshot(&mut self, _snapshot: &Snapshot) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn sync(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps the snapshot and the entries in memory, which survive an
+/// engine but not the process.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStorage {
+    pub snapshot: Option<Snapshot>,
+    pub entries: Vec<WalEntry>,
+    /// Amount of entries after which a snapshot is taken.
+    /// `0` disables the snapshots.
+    pub snapshot_every: usize,
+}
+
+impl Storage for MemoryStorage {
+    fn recover(&mut self) -> anyhow::Result<(Option<Snapshot>, Vec<WalEntry>)> {
+        Ok((self.snapshot.clone(), self.entries.clone()))
+    }
+    fn append(&mut self, entry: &WalEntry) -> anyhow::Result<()> {
+        self.entries.push(entry.clone());
+        Ok(())
+    }
+    fn wants_snapshot(&self) -> bool {
+        self.snapshot_every != 0 && self.entries.len() >= self.snapshot_every
+    }
+    fn snapshot(&mut self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        self.snapshot = Some(snapshot.clone());
+        self.entries.clear();
+        Ok(())
+    }
+    fn sync(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Persists the state into a directory, as a json snapshot file and a
+/// json-lines WAL file.
+///
+/// Each entry is handed to the OS as soon as it's appended, so a crash
+/// of the process doesn't lose it. The files are only synced into the
+/// disk on snapshots and on `sync`, so a system crash may lose the last
+/// entries, in which case their transactions are processed again from
+/// the input when resuming.
+/// An entry that was only partially written is discarded on recovery.
+#[derive(Debug)]
+pub struct DiskStorage {
+    dir: PathBuf,
+    wal: Option<io::BufWriter<fs::File>>,
+    entries: usize,
+    /// Amount of entries after which a snapshot is taken.
+    /// `0` disables the snapshots.
+    pub snapshot_every: usize,
+}
+
+impl DiskStorage {
+    const SNAPSHOT: &'static str = "snapshot.json";
+    const WAL: &'static str = "wal.jsonl";
+
+    /// Uses the directory as storage, creating it if needed.
+    pub fn open(dir: impl AsRef<Path>, snapshot_every: usize) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            wal: None,
+            entries: 0,
+            snapshot_every,
+        })
+    }
+
+    fn wal(&mut self) -> anyhow::Result<&mut io::BufWriter<fs::File>> {
+        if self.wal.is_none() {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(Self::WAL))?;
+            self.wal = Some(io::BufWriter::new(file));
+        }
+        Ok(self.wal.as_mut().unwrap())
+    }
+}
+
+impl Storage for DiskStorage {
+    fn recover(&mut self) -> anyhow::Result<(Option<Snapshot>, Vec<WalEntry>)> {
+        let path = self.dir.join(Self::SNAPSHOT);
+        let snapshot = match fs::File::open(&path) {
+            Ok(file) => Some(Snapshot::read(file)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = vec![];
+        let path = self.dir.join(Self::WAL);
+        let mut file = match fs::OpenOptions::new().read(true).write(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((snapshot, entries)),
+            Err(e) => return Err(e.into()),
+        };
+        // the length of the complete entries
+        let mut valid_len = 0;
+        let mut reader = io::BufReader::new(&mut file);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let len = reader.read_line(&mut line)?;
+            if len == 0 || !line.ends_with('\n') {
+                break;
+            }
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(_) => break,
+            }
+            valid_len += len as u64;
+        }
+        drop(reader);
+        // discards a partially written entry, so the next appended
+        // entries start on a new line
+        if file.metadata()?.len() != valid_len {
+            tracing::warn!("Discarding a partially written WAL entry");
+            file.set_len(valid_len)?;
+            file.sync_data()?;
+        }
+        self.entries = entries.len();
+        Ok((snapshot, entries))
+    }
+
+    fn append(&mut self, entry: &WalEntry) -> anyhow::Result<()> {
+        let wal = self.wal()?;
+        serde_json::to_writer(&mut *wal, entry)?;
+        wal.write_all(b"\n")?;
+        wal.flush()?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    fn wants_snapshot(&self) -> bool {
+        self.snapshot_every != 0 && self.entries >= self.snapshot_every
+    }
+
+    fn snapshot(&mut self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        snapshot.save(self.dir.join(Self::SNAPSHOT))?;
+
+        // if this is interrupted, the remaining entries are skipped on
+        // recovery, as they are older than the snapshot
+        let wal = self.wal()?;
+        wal.flush()?;
+        wal.get_ref().set_len(0)?;
+        wal.get_ref().sync_data()?;
+        self.entries = 0;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        if let Some(wal) = &mut self.wal {
+            wal.flush()?;
+            wal.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+}
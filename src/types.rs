@@ -6,7 +6,7 @@ use derive_more as dm;
 use rust_decimal as dec;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-pub use tx::{ExternalTx, OrderedTxs, Tx, TxId};
+pub use tx::{ExternalTx, Tx, TxId, Txs};
 
 #[derive(
     Clone,
@@ -29,6 +29,27 @@ pub use tx::{ExternalTx, OrderedTxs, Tx, TxId};
 #[serde(transparent)]
 pub struct ClientId(u16);
 
+/// A currency or asset, such as `EUR` or `BTC`.
+///
+/// Transactions without an asset are of the default one.
+#[derive(
+    Clone,
+    Debug,
+    Default,
+    Hash,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    dm::From,
+    dm::Into,
+    dm::Display,
+    Serialize,
+    Deserialize,
+)]
+#[serde(transparent)]
+pub struct Asset(String);
+
 #[derive(
     Clone,
     Debug,
@@ -38,10 +59,9 @@ pub struct ClientId(u16);
     PartialEq,
     Ord,
     PartialOrd,
-    dm::Add,
-    dm::AddAssign,
     dm::From,
     dm::Into,
+    dm::Display,
     Serialize,
     Deserialize,
 )]
@@ -52,6 +72,10 @@ pub struct Amount(dec::Decimal);
 #[error("Rhs is too big on the subtraction {0:?} - {1:?}")]
 pub struct RhsSubTooBigError(Amount, Amount);
 
+#[derive(Clone, Debug, Eq, PartialEq, Error)]
+#[error("Overflow on the addition {0:?} + {1:?}")]
+pub struct AddOverflowError(Amount, Amount);
+
 impl Amount {
     pub fn sufficient_sub(&mut self, rhs: &Self) -> Result<(), RhsSubTooBigError> {
         if *self >= *rhs {
@@ -61,4 +85,79 @@ impl Amount {
             Err(RhsSubTooBigError(self.clone(), rhs.clone()))
         }
     }
+
+    /// Adds into `self`, unless the result would overflow.
+    pub fn checked_add(&mut self, rhs: &Self) -> Result<(), AddOverflowError> {
+        match self.0.checked_add(rhs.0) {
+            Some(sum) => {
+                self.0 = sum;
+                Ok(())
+            }
+            None => Err(AddOverflowError(self.clone(), rhs.clone())),
+        }
+    }
+
+    /// Subtracts from `self`, which may turn it negative, unless the
+    /// result would overflow (reported as the addition of `-rhs`).
+    pub fn checked_sub(&mut self, rhs: &Self) -> Result<(), AddOverflowError> {
+        self.checked_add(&Amount(-rhs.0))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        !self.0.is_zero() && self.0.is_sign_negative()
+    }
+
+    /// The amount of decimals, not counting the trailing zeros.
+    pub fn decimals(&self) -> u32 {
+        self.0.normalize().scale()
+    }
+
+    /// The amount of decimals as written, including the trailing zeros.
+    pub fn scale(&self) -> u32 {
+        self.0.scale()
+    }
+
+    /// The `percentage` of the amount, unless it would overflow.
+    pub fn percentage(&self, percentage: &Self) -> Option<Self> {
+        let hundred = dec::Decimal::new(100, 0);
+        Some(Self(
+            self.0.checked_mul(percentage.0)?.checked_div(hundred)?,
+        ))
+    }
+
+    /// Rounds (half to even) into at most `decimals` decimals.
+    pub fn round(&self, decimals: u32) -> Self {
+        Self(self.0.round_dp(decimals))
+    }
+
+    /// Changes the scale into exactly `decimals` decimals, such as `1.50`
+    /// for `1.5` with two decimals, rounding if needed.
+    pub fn rescale(&mut self, decimals: u32) {
+        self.0.rescale(decimals);
+    }
+
+    /// The amount as an integer of `10^-scale` units, if it has at most
+    /// `scale` decimals and fits.
+    pub fn to_scaled(&self, scale: u32) -> Option<i64> {
+        use dec::prelude::ToPrimitive;
+        let scaled = self
+            .0
+            .checked_mul(dec::Decimal::new(10i64.checked_pow(scale)?, 0))?;
+        if !scaled.fract().is_zero() {
+            return None;
+        }
+        scaled.to_i64()
+    }
+
+    /// The amount from an integer of `10^-scale` units.
+    ///
+    /// The trailing zeros are dropped, so the amount is shown the same as
+    /// when read from a csv input.
+    pub fn from_scaled(value: i64, scale: u32) -> Self {
+        Self(dec::Decimal::new(value, scale).normalize())
+    }
 }
@@ -1,13 +1,13 @@
-use crate::{err, try_on, Apply, TResult};
+use crate::{err, try_on, Apply, Policy, TResult, Token};
 use crate::{
     types::{
         tx::{self, TxType, Txs},
-        Amount, ClientId, ExternalTx, RhsSubTooBigError, TxId,
+        AddOverflowError, Amount, Asset, ClientId, ExternalTx, RhsSubTooBigError, TxId,
     },
     TP,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use thiserror::Error;
 
 pub type Clients = HashMap<ClientId, Client>;
@@ -20,56 +20,413 @@ pub struct Client {
     pub available: Amount,
     pub held: Amount,
     pub total: Amount,
+    pub status: AccountStatus,
+    /// How far below zero the `available` of the default asset may go.
+    #[serde(default, skip_serializing_if = "Amount::is_zero")]
+    pub credit_limit: Amount,
+    /// The balances of the assets other than the default one, which is
+    /// the one above.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub assets: BTreeMap<Asset, Balance>,
+}
+
+/// The balance of a client on an asset.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+}
+
+/// Mutable access into the balance of a client on an asset.
+pub struct BalanceMut<'c> {
+    pub available: &'c mut Amount,
+    pub held: &'c mut Amount,
+    pub total: &'c mut Amount,
+}
+
+/// The status of a client's account, which limits the transactions that
+/// it accepts (see `AccountStatus::accepts`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    /// Locked by a chargeback.
+    Locked,
+    /// Frozen by an admin.
+    Frozen,
+    /// Closed by an admin, which is final.
+    Closed,
+}
+
+impl AccountStatus {
+    /// Whether an account in this status accepts a transaction of this
+    /// type:
+    ///
+    /// | tx type                                  | active | locked | frozen | closed |
+    /// |------------------------------------------|--------|--------|--------|--------|
+    /// | deposit, withdrawal, transfer, authorize | yes    | no     | no     | no     |
+    /// | dispute, resolve, chargeback             | yes    | yes    | yes    | no     |
+    /// | capture, void                            | yes    | yes    | yes    | no     |
+    /// | unlock                                   | no     | yes    | yes    | no     |
+    /// | freeze                                   | yes    | no     | no     | no     |
+    /// | close (without held funds)               | yes    | yes    | yes    | no     |
+    /// | limit                                    | yes    | yes    | yes    | no     |
+    ///
+    /// The destination of a transfer is checked as for a deposit, and
+    /// the destination of a disputed transfer as for the dispute.
+    /// The policy may replace this table (see `Policy::check_status`).
+    pub fn accepts(&self, ty: &TxType) -> bool {
+        use AccountStatus::*;
+        match (self, ty) {
+            (Closed, _) => false,
+            (Active, TxType::Unlock) => false,
+            (Active, _) => true,
+            (
+                _,
+                TxType::Deposit
+                | TxType::Withdrawal
+                | TxType::Transfer
+                | TxType::Authorize
+                | TxType::Freeze,
+            ) => false,
+            (_, _) => true,
+        }
+    }
+
+    /// Checks that the account accepts a transaction of this type.
+    pub fn check(&self, ty: &TxType) -> Result<(), ClTxError> {
+        if self.accepts(ty) {
+            return Ok(());
+        }
+        Err(self.error())
+    }
+
+    /// The error of a transaction that isn't accepted in this status.
+    pub fn error(&self) -> ClTxError {
+        match self {
+            AccountStatus::Active => ClTxError::ActiveClientError,
+            AccountStatus::Locked => ClTxError::LockedClientError,
+            AccountStatus::Frozen => ClTxError::FrozenClientError,
+            AccountStatus::Closed => ClTxError::ClosedClientError,
+        }
+    }
+}
+
+/// A client as shown in the outputs, where any status other than
+/// `Active` is shown as `locked`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ClientRow {
+    pub client: ClientId,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    /// Only shown when some client has a credit limit, see `OutputRow`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credit_limit: Option<Amount>,
+    /// The amount owed, if the total is negative. In the outputs, only
+    /// shown when some client is in debt, see `OutputRow`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debt: Option<Amount>,
+}
+
+impl From<Client> for ClientRow {
+    fn from(client: Client) -> Self {
+        let debt = client.debt();
+        ClientRow {
+            client: client.id,
+            available: client.available,
+            held: client.held,
+            total: client.total,
+            locked: client.status != AccountStatus::Active,
+            credit_limit: None,
+            debt,
+        }
+    }
+}
+
+/// A client's balance on an asset, as shown in the outputs when there
+/// are many assets, where the default asset is shown as empty.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AssetRow {
+    pub client: ClientId,
+    pub asset: Option<Asset>,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
     pub locked: bool,
+    /// Only shown when some client has a credit limit, see `OutputRow`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credit_limit: Option<Amount>,
+    /// The amount owed, if the total is negative. In the outputs, only
+    /// shown when some client is in debt, see `OutputRow`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debt: Option<Amount>,
 }
 
-#[derive(Debug, Error)]
+impl AssetRow {
+    /// The rows of each asset of the client.
+    ///
+    /// The default asset is omitted when the client only has other
+    /// assets.
+    pub fn rows(client: Client) -> Vec<Self> {
+        let locked = client.status != AccountStatus::Active;
+        let id = client.id;
+        let default = Balance {
+            available: client.available,
+            held: client.held,
+            total: client.total,
+        };
+        let mut rows = vec![];
+        if client.assets.is_empty() || default != Balance::default() {
+            rows.push((None, default));
+        }
+        rows.extend(client.assets.into_iter().map(|(asset, b)| (Some(asset), b)));
+        rows.into_iter()
+            .map(|(asset, balance)| AssetRow {
+                client: id.clone(),
+                debt: debt(&balance.total),
+                asset,
+                available: balance.available,
+                held: balance.held,
+                total: balance.total,
+                locked,
+                credit_limit: None,
+            })
+            .collect()
+    }
+}
+
+/// The amount owed on a negative `total`.
+fn debt(total: &Amount) -> Option<Amount> {
+    if total.is_negative() {
+        let mut debt = Amount::default();
+        debt.checked_sub(total).ok()?;
+        Some(debt)
+    } else {
+        None
+    }
+}
+
+/// A row of the outputs, which all have the same columns.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum OutputRow {
+    Client(ClientRow),
+    Asset(AssetRow),
+}
+
+impl OutputRow {
+    /// The rows of all clients, which have the `asset` column only if
+    /// some client has other assets, the `credit_limit` column only if
+    /// some client has a credit limit, and the `debt` column only if some
+    /// client is in debt.
+    pub fn all(clients: Vec<Client>) -> Vec<Self> {
+        let with_assets = clients.iter().any(|client| !client.assets.is_empty());
+        let with_limits = clients.iter().any(|client| !client.credit_limit.is_zero());
+        let mut rows = vec![];
+        for client in clients {
+            let limit = Some(client.credit_limit.clone()).filter(|_| with_limits);
+            if with_assets {
+                rows.extend(AssetRow::rows(client).into_iter().map(|mut row| {
+                    row.credit_limit = limit.clone();
+                    OutputRow::Asset(row)
+                }));
+            } else {
+                let mut row = ClientRow::from(client);
+                row.credit_limit = limit;
+                rows.push(OutputRow::Client(row));
+            }
+        }
+        let with_debts = rows.iter_mut().any(|row| row.debt_mut().is_some());
+        if with_debts {
+            for row in &mut rows {
+                row.debt_mut().get_or_insert_with(Amount::default);
+            }
+        }
+        rows
+    }
+
+    fn debt_mut(&mut self) -> &mut Option<Amount> {
+        match self {
+            OutputRow::Client(row) => &mut row.debt,
+            OutputRow::Asset(row) => &mut row.debt,
+        }
+    }
+}
+
+/// Errors from a client processing a transaction.
+///
+/// Each variant has a stable code (see `ClTxError::code`), which is also
+/// used as the `code` tag when the error is serialized.
+#[derive(Clone, Debug, PartialEq, Eq, Error, Serialize)]
+#[serde(tag = "code", content = "details")]
 pub enum ClTxError {
     #[error("Incoming tx is missing the amount field")]
+    #[serde(rename = "E_MISSING_AMOUNT")]
     MissingAmountError,
     #[error(
         "Incoming tx requires a subtraction from insufficient founds. Subtraction is {0:?} - {1:?}"
     )]
+    #[serde(rename = "E_INSUFFICIENT_FUNDS")]
     InsufficientFoundsError(Amount, Amount),
     #[error("Incoming tx has the amount field when none was expected. Found: {0:?}")]
+    #[serde(rename = "E_UNEXPECTED_AMOUNT")]
     ExpectingEmptyAmountError(Amount),
+    #[error("Incoming tx has a negative amount {0:?}")]
+    #[serde(rename = "E_NEGATIVE_AMOUNT")]
+    NegativeAmountError(Amount),
+    #[error("Incoming tx has a zero amount")]
+    #[serde(rename = "E_ZERO_AMOUNT")]
+    ZeroAmountError,
+    #[error("Incoming tx requires an addition that overflows. Addition is {0:?} + {1:?}")]
+    #[serde(rename = "E_AMOUNT_OVERFLOW")]
+    AmountOverflowError(Amount, Amount),
+    #[error("Incoming tx amount {amount:?} has more than {decimals} decimals")]
+    #[serde(rename = "E_TOO_MANY_DECIMALS")]
+    TooManyDecimalsError { amount: Amount, decimals: u32 },
     //
     #[error("Incoming tx indicates a tx of another client. Incoming tx client: {incoming:?}, indicated tx client: {stored:?}")]
+    #[serde(rename = "E_DIFFERENT_CLIENT")]
     DifferentClientError {
         incoming: ClientId,
         stored: ClientId,
     },
     #[error("The client is locked")]
+    #[serde(rename = "E_LOCKED_CLIENT")]
     LockedClientError,
+    #[error("The client is frozen")]
+    #[serde(rename = "E_FROZEN_CLIENT")]
+    FrozenClientError,
+    #[error("The client is closed")]
+    #[serde(rename = "E_CLOSED_CLIENT")]
+    ClosedClientError,
+    #[error("The client is already active")]
+    #[serde(rename = "E_ACTIVE_CLIENT")]
+    ActiveClientError,
+    #[error("Incoming admin tx is missing the reason field")]
+    #[serde(rename = "E_MISSING_REASON")]
+    MissingReasonError,
+    #[error("The client can't be closed while it has held funds {held:?} of the asset {asset:?}")]
+    #[serde(rename = "E_CLOSE_HELD_FUNDS")]
+    ClosingWithHeldFundsError { asset: Option<Asset>, held: Amount },
+    #[error("Incoming transfer is missing the destination field")]
+    #[serde(rename = "E_MISSING_DESTINATION")]
+    MissingDestinationError,
+    #[error("Incoming transfer has its own client as the destination")]
+    #[serde(rename = "E_SELF_TRANSFER")]
+    SelfTransferError,
+    #[error("Transfers are not supported when the clients are sharded into many workers")]
+    #[serde(rename = "E_TRANSFER_UNSUPPORTED")]
+    TransferUnsupportedError,
+    #[error("Incoming tx indicates a non-existent hold {0:?}")]
+    #[serde(rename = "E_HOLD_NOT_FOUND")]
+    HoldNotFoundError(TxId),
+    #[error("Incoming tx indicates a non-authorize tx {0:?}")]
+    #[serde(rename = "E_NOT_A_HOLD")]
+    NonHoldError(TxId),
+    #[error("Incoming tx indicates an already captured, voided or expired hold {0:?}")]
+    #[serde(rename = "E_HOLD_SETTLED")]
+    HoldSettledError(TxId),
+    #[error(
+        "Incoming tx requires a debit of {amount:?} beyond the client's credit limit {limit:?}"
+    )]
+    #[serde(rename = "E_CREDIT_LIMIT_EXCEEDED")]
+    CreditLimitExceededError { amount: Amount, limit: Amount },
+    #[error("Incoming {} tx can't be processed this way", .0.as_str())]
+    #[serde(rename = "E_UNEXPECTED_TX_TYPE")]
+    UnexpectedTxTypeError(TxType),
+    #[error("Incoming tx reuses the id of an already stored tx {0:?}")]
+    #[serde(rename = "E_DUPLICATE_TX")]
+    DuplicateTxIdError(TxId),
     //
     #[error("Incoming tx indicates a non-existent tx {0:?}")]
+    #[serde(rename = "E_DISPUTE_TX_NOT_FOUND")]
     DisputationOnANotFoundTxIdError(TxId),
     #[error("Incoming tx indicates a non-deposit tx {0:?}")]
+    #[serde(rename = "E_DISPUTE_NON_DEPOSIT")]
     DisputationOnNonDepositError(TxId),
     #[error("Incoming tx indicates an already disputed tx {0:?}")]
+    #[serde(rename = "E_DISPUTE_ALREADY_DISPUTED")]
     DisputationOnAlreadyDisputedTxError(TxId),
     //
     #[error("Incoming tx indicates a non-existent tx {0:?}")]
+    #[serde(rename = "E_RESOLVE_TX_NOT_FOUND")]
     ResolvingOnANotFoundTxIdError(TxId),
     #[error("Incoming tx indicates a non-deposit tx {0:?}")]
+    #[serde(rename = "E_RESOLVE_NON_DEPOSIT")]
     ResolvingOnNonDepositError(TxId),
     #[error("Incoming tx indicates an non-disputed tx {0:?}")]
+    #[serde(rename = "E_RESOLVE_NOT_DISPUTED")]
     ResolvingOnNonDisputedTxError(TxId),
     //
     #[error("Incoming tx indicates a non-existent tx {0:?}")]
+    #[serde(rename = "E_CHARGEBACK_TX_NOT_FOUND")]
     ChargebackOnANotFoundTxIdError(TxId),
     #[error("Incoming tx indicates a non-deposit tx {0:?}")]
+    #[serde(rename = "E_CHARGEBACK_NON_DEPOSIT")]
     ChargebackOnNonDepositError(TxId),
     #[error("Incoming tx indicates an non-disputed tx {0:?}")]
+    #[serde(rename = "E_CHARGEBACK_NOT_DISPUTED")]
     ChargebackOnNonDisputedTxError(TxId),
 }
 
+impl ClTxError {
+    /// A stable and machine-readable code that identifies the error
+    /// variant.
+    pub fn code(&self) -> &'static str {
+        use ClTxError::*;
+        match self {
+            MissingAmountError => "E_MISSING_AMOUNT",
+            InsufficientFoundsError(..) => "E_INSUFFICIENT_FUNDS",
+            ExpectingEmptyAmountError(..) => "E_UNEXPECTED_AMOUNT",
+            NegativeAmountError(..) => "E_NEGATIVE_AMOUNT",
+            ZeroAmountError => "E_ZERO_AMOUNT",
+            AmountOverflowError(..) => "E_AMOUNT_OVERFLOW",
+            TooManyDecimalsError { .. } => "E_TOO_MANY_DECIMALS",
+            DifferentClientError { .. } => "E_DIFFERENT_CLIENT",
+            LockedClientError => "E_LOCKED_CLIENT",
+            FrozenClientError => "E_FROZEN_CLIENT",
+            ClosedClientError => "E_CLOSED_CLIENT",
+            ActiveClientError => "E_ACTIVE_CLIENT",
+            MissingReasonError => "E_MISSING_REASON",
+            ClosingWithHeldFundsError { .. } => "E_CLOSE_HELD_FUNDS",
+            MissingDestinationError => "E_MISSING_DESTINATION",
+            SelfTransferError => "E_SELF_TRANSFER",
+            TransferUnsupportedError => "E_TRANSFER_UNSUPPORTED",
+            HoldNotFoundError(..) => "E_HOLD_NOT_FOUND",
+            NonHoldError(..) => "E_NOT_A_HOLD",
+            HoldSettledError(..) => "E_HOLD_SETTLED",
+            CreditLimitExceededError { .. } => "E_CREDIT_LIMIT_EXCEEDED",
+            UnexpectedTxTypeError(..) => "E_UNEXPECTED_TX_TYPE",
+            DuplicateTxIdError(..) => "E_DUPLICATE_TX",
+            DisputationOnANotFoundTxIdError(..) => "E_DISPUTE_TX_NOT_FOUND",
+            DisputationOnNonDepositError(..) => "E_DISPUTE_NON_DEPOSIT",
+            DisputationOnAlreadyDisputedTxError(..) => "E_DISPUTE_ALREADY_DISPUTED",
+            ResolvingOnANotFoundTxIdError(..) => "E_RESOLVE_TX_NOT_FOUND",
+            ResolvingOnNonDepositError(..) => "E_RESOLVE_NON_DEPOSIT",
+            ResolvingOnNonDisputedTxError(..) => "E_RESOLVE_NOT_DISPUTED",
+            ChargebackOnANotFoundTxIdError(..) => "E_CHARGEBACK_TX_NOT_FOUND",
+            ChargebackOnNonDepositError(..) => "E_CHARGEBACK_NON_DEPOSIT",
+            ChargebackOnNonDisputedTxError(..) => "E_CHARGEBACK_NOT_DISPUTED",
+        }
+    }
+}
+
 impl From<RhsSubTooBigError> for ClTxError {
     fn from(e: RhsSubTooBigError) -> Self {
         ClTxError::InsufficientFoundsError(e.0, e.1)
     }
 }
 
+impl From<AddOverflowError> for ClTxError {
+    fn from(e: AddOverflowError) -> Self {
+        ClTxError::AmountOverflowError(e.0, e.1)
+    }
+}
+
 impl Client {
     pub fn new(id: &ClientId) -> Self {
         Client {
@@ -77,6 +434,50 @@ impl Client {
             ..Self::default()
         }
     }
+    /// The amount owed by the client, if its total (of the default asset)
+    /// is negative.
+    ///
+    /// See `Policy::signed_balances`.
+    pub fn debt(&self) -> Option<Amount> {
+        debt(&self.total)
+    }
+    /// The balance on the `asset`, or on the default one.
+    pub fn balance_mut(&mut self, asset: Option<&Asset>) -> BalanceMut<'_> {
+        match asset {
+            None => BalanceMut {
+                available: &mut self.available,
+                held: &mut self.held,
+                total: &mut self.total,
+            },
+            Some(asset) => {
+                let balance = self.assets.entry(asset.clone()).or_default();
+                BalanceMut {
+                    available: &mut balance.available,
+                    held: &mut balance.held,
+                    total: &mut balance.total,
+                }
+            }
+        }
+    }
+    /// The first asset that has held funds, if any, where the default
+    /// asset is `None`.
+    pub fn held_funds(&self) -> Option<(Option<&Asset>, &Amount)> {
+        let assets = self.assets.iter().map(|(asset, b)| (Some(asset), &b.held));
+        std::iter::once((None, &self.held))
+            .chain(assets)
+            .find(|(_, held)| !held.is_zero())
+    }
+    /// Shows the amounts with exactly `decimals` decimals.
+    pub fn rescale(&mut self, decimals: u32) {
+        self.available.rescale(decimals);
+        self.held.rescale(decimals);
+        self.total.rescale(decimals);
+        for balance in self.assets.values_mut() {
+            balance.available.rescale(decimals);
+            balance.held.rescale(decimals);
+            balance.total.rescale(decimals);
+        }
+    }
     pub fn check_client_id(&self, tx: &tx::Tx) -> Result<(), ClTxError> {
         if self.id == tx.client {
             Ok(())
@@ -88,40 +489,189 @@ impl Client {
         }
     }
 
+    /// Checks that the client is the destination of the transfer `tx`.
+    pub fn check_destination(&self, tx: &tx::Tx) -> Result<(), ClTxError> {
+        match &tx.destination {
+            Some(destination) if destination == &self.id => Ok(()),
+            other => Err(ClTxError::DifferentClientError {
+                incoming: self.id.clone(),
+                stored: other.clone().unwrap_or_else(|| tx.client.clone()),
+            }),
+        }
+    }
+
+    /// Debits `available` and `total`, where the `available` of the
+    /// default asset may go negative down to the `credit_limit`.
+    fn debit(&mut self, asset: Option<&Asset>, amount: &Amount) -> Result<(), ClTxError> {
+        let limit = match asset {
+            None if !self.credit_limit.is_zero() => self.credit_limit.clone(),
+            _ => {
+                let balance = self.balance_mut(asset);
+                balance.available.sufficient_sub(amount)?;
+                balance.total.sufficient_sub(amount)?;
+                return Ok(());
+            }
+        };
+        let balance = self.balance_mut(asset);
+        let mut credit = balance.available.clone();
+        credit.checked_add(&limit)?;
+        if credit < *amount {
+            let amount = amount.clone();
+            return Err(ClTxError::CreditLimitExceededError { amount, limit });
+        }
+        balance.available.checked_sub(amount)?;
+        balance.total.checked_sub(amount)?;
+        Ok(())
+    }
+
+    /// Debits a withdrawal.
+    fn withdraw(&mut self, asset: Option<&Asset>, amount: &Amount) -> Result<(), ClTxError> {
+        self.debit(asset, amount)
+    }
+
+    /// Whether `extx` is an accepted idempotent replay of a stored tx,
+    /// or an error if it otherwise reuses a stored tx id.
+    fn replayed(txs: &Txs, extx: &ExternalTx, policy: &Policy) -> Result<bool, ClTxError> {
+        let stored = match extx.ty {
+            TxType::Deposit | TxType::Withdrawal | TxType::Transfer | TxType::Authorize => {
+                txs.get(&extx.txid)
+            }
+            _ => None,
+        };
+        match stored.map(|stored| stored.is_identical(extx)) {
+            None => Ok(false),
+            Some(true) if policy.idempotent_replays => Ok(true),
+            Some(_) => Err(ClTxError::DuplicateTxIdError(extx.txid.clone())),
+        }
+    }
+
+    /// The stored tx, disputed by this client, that a chargeback of
+    /// `txid` reverses.
+    fn chargeback_tx<'a>(
+        &self,
+        txid: &TxId,
+        txs: &'a Txs,
+        policy: &Policy,
+    ) -> Result<&'a tx::Tx, ClTxError> {
+        use ClTxError::*;
+        let tx = txs
+            .get(txid)
+            .ok_or_else(|| ChargebackOnANotFoundTxIdError(txid.clone()))?;
+        if !policy.is_disputable(&tx.ty) {
+            return Err(ChargebackOnNonDepositError(txid.clone()));
+        };
+        if !tx.is_disputed() {
+            return Err(ChargebackOnNonDisputedTxError(txid.clone()));
+        };
+        self.check_client_id(tx)?;
+        if tx.amount.is_none() {
+            return Err(MissingAmountError);
+        }
+        Ok(tx)
+    }
+
+    /// Reverses the disputed `tx`, and locks the client.
+    fn charge_back(&mut self, tx: &tx::Tx, policy: &Policy) -> Result<(), ClTxError> {
+        let amount = tx.amount.as_ref().ok_or(ClTxError::MissingAmountError)?;
+        let balance = self.balance_mut(tx.asset.as_ref());
+        balance.held.sufficient_sub(amount)?;
+        if tx.ty == TxType::Withdrawal {
+            // the withdrawal is reversed, so it's credited back
+            balance.available.checked_add(amount)?;
+        } else if policy.signed_balances {
+            // which may leave a debt
+            balance.total.checked_sub(amount)?;
+        } else {
+            balance.total.sufficient_sub(amount)?;
+        }
+        self.status = AccountStatus::Locked;
+        Ok(())
+    }
+
+    /// The part of the `fee` of the chargeback of `tx` that the client can
+    /// still pay once charged back, as the chargeback itself can't be
+    /// refused.
+    ///
+    /// Under `Policy::signed_balances`, the whole fee is charged, which
+    /// may leave a debt instead.
+    fn affordable_fee(&self, tx: &tx::Tx, fee: &Amount, policy: &Policy) -> Amount {
+        let mut next = self.clone();
+        // a failing chargeback is rejected regardless of its fee
+        if policy.signed_balances || next.charge_back(tx, policy).is_err() {
+            return fee.clone();
+        }
+        let limit = match &tx.asset {
+            None => next.credit_limit.clone(),
+            Some(_) => Amount::default(),
+        };
+        let mut affordable = next.balance_mut(tx.asset.as_ref()).available.clone();
+        if affordable.checked_add(&limit).is_err() || &affordable >= fee {
+            fee.clone()
+        } else if affordable.is_negative() {
+            Amount::default()
+        } else {
+            affordable
+        }
+    }
+
+    /// Debits a fee, which may only leave a debt under
+    /// `Policy::signed_balances`.
+    fn pay_fee(
+        &mut self,
+        asset: Option<&Asset>,
+        fee: &Amount,
+        policy: &Policy,
+    ) -> Result<(), ClTxError> {
+        if !policy.signed_balances {
+            return self.debit(asset, fee);
+        }
+        let balance = self.balance_mut(asset);
+        balance.available.checked_sub(fee)?;
+        balance.total.checked_sub(fee)?;
+        Ok(())
+    }
+
     pub fn try_process_transaction<'t>(
         client: TP<'t, Client>,
         extx: &'t ExternalTx,
         previous_txs: TP<'t, Txs>,
+        policy: &Policy,
     ) -> TResult<'t, (Client, Txs), ClTxError> {
         use ClTxError::*;
+
+        let replayed = Client::replayed(previous_txs.as_ref(), extx, policy);
+        if try_on!(replayed, client, previous_txs) {
+            // an exact resubmission is accepted without changes
+            return Ok(client.consume().then(previous_txs.consume()));
+        }
+
+        let check = policy.check_status(&client.as_ref().status, &extx.ty);
+        try_on!(check, client, previous_txs);
+
         match &extx.ty {
             TxType::Deposit => {
                 let amount = extx.amount.as_ref().ok_or(MissingAmountError);
                 let amount = try_on!(amount, client, previous_txs);
-                client
-                    .prepare(move |next: &mut Client| {
-                        next.available += amount.clone();
-                        next.total += amount.clone();
-                        Ok(())
-                    })
-                    .chain(previous_txs.skip())
-                    .apply()
+                let asset = &extx.asset;
+                let client = client.prepare(move |next: &mut Client| {
+                    let balance = next.balance_mut(asset.as_ref());
+                    balance.available.checked_add(amount)?;
+                    balance.total.checked_add(amount)?;
+                    Ok(())
+                });
+
+                previous_txs.skip_after(client.apply())
             }
             TxType::Withdrawal => {
                 let amount = extx.amount.as_ref().ok_or(MissingAmountError);
                 let amount = try_on!(amount, client, previous_txs);
-                if client.as_ref().locked {
-                    let err = LockedClientError;
-                    return err!(err, client, previous_txs);
-                }
-
+                let asset = &extx.asset;
                 let client = client.prepare(move |next: &mut Client| {
-                    next.available.sufficient_sub(amount)?;
-                    next.total.sufficient_sub(amount)?;
+                    next.withdraw(asset.as_ref(), amount)?;
                     Ok(())
                 });
 
-                client.chain(previous_txs.skip()).apply()
+                previous_txs.skip_after(client.apply())
             }
             TxType::Dispute => {
                 if let Some(ref amount) = extx.amount {
@@ -140,7 +690,7 @@ impl Client {
                     }
                 };
 
-                if TxType::Deposit != disputing_tx.as_ref().ty {
+                if !policy.is_disputable(&disputing_tx.as_ref().ty) {
                     let err = DisputationOnNonDepositError(txid.clone());
                     return err!(err, client, tx_upper.returned(disputing_tx));
                 };
@@ -154,10 +704,22 @@ impl Client {
                     .as_ref()
                     .ok_or(MissingAmountError);
                 let amount = try_on!(amount, client, tx_upper.returned(disputing_tx)).clone();
+                let ty = disputing_tx.as_ref().ty.clone();
+                let asset = disputing_tx.as_ref().asset.clone();
 
                 let client = client.prepare::<_, ClTxError>(|next: &mut Client| {
-                    next.available.sufficient_sub(&amount)?;
-                    next.held += amount.clone();
+                    let balance = next.balance_mut(asset.as_ref());
+                    if ty == TxType::Withdrawal {
+                        // the withdrawn funds are returned, pending resolution
+                        balance.held.checked_add(&amount)?;
+                        balance.total.checked_add(&amount)?;
+                    } else if policy.signed_balances {
+                        balance.available.checked_sub(&amount)?;
+                        balance.held.checked_add(&amount)?;
+                    } else {
+                        balance.available.sufficient_sub(&amount)?;
+                        balance.held.checked_add(&amount)?;
+                    }
                     Ok(())
                 });
 
@@ -189,11 +751,16 @@ impl Client {
                 let (tx_upper, resolving_tx) = match previous_txs.get_mut(&extx.txid) {
                     Ok(ok) => ok,
                     Err(previous_txs) => {
-                        let err = ResolvingOnNonDepositError(txid.clone());
+                        let err = ResolvingOnANotFoundTxIdError(txid.clone());
                         return err!(err, client, previous_txs);
                     }
                 };
 
+                if !policy.is_disputable(&resolving_tx.as_ref().ty) {
+                    let err = ResolvingOnNonDepositError(txid.clone());
+                    return err!(err, client, tx_upper.returned(resolving_tx));
+                }
+
                 let check = client.as_ref().check_client_id(resolving_tx.as_ref());
                 try_on!(check, client, tx_upper.returned(resolving_tx));
 
@@ -203,10 +770,18 @@ impl Client {
                     .as_ref()
                     .ok_or(MissingAmountError);
                 let amount = try_on!(amount, client, tx_upper.returned(resolving_tx)).clone();
+                let ty = resolving_tx.as_ref().ty.clone();
+                let asset = resolving_tx.as_ref().asset.clone();
 
                 let client = client.prepare::<_, ClTxError>(|next: &mut Client| {
-                    next.held.sufficient_sub(&amount)?;
-                    next.available += amount.clone();
+                    let balance = next.balance_mut(asset.as_ref());
+                    balance.held.sufficient_sub(&amount)?;
+                    if ty == TxType::Withdrawal {
+                        // the withdrawal stands, so it's re-debited
+                        balance.total.sufficient_sub(&amount)?;
+                    } else {
+                        balance.available.checked_add(&amount)?;
+                    }
                     Ok(())
                 });
 
@@ -232,38 +807,453 @@ impl Client {
                     return err!(err, client, previous_txs);
                 };
 
-                // extx and chargeback_tx would have the same txid information
-                let txid = &extx.txid;
+                let chargeback_tx =
+                    client
+                        .as_ref()
+                        .chargeback_tx(&extx.txid, previous_txs.as_ref(), policy);
+                let chargeback_tx = try_on!(chargeback_tx, client, previous_txs).clone();
 
-                let chargeback_tx = previous_txs
-                    .as_ref()
-                    .get(&extx.txid)
-                    .ok_or_else(|| ChargebackOnANotFoundTxIdError(txid.clone()));
-                let chargeback_tx = try_on!(chargeback_tx, client, previous_txs);
+                let client = client.prepare::<_, ClTxError>(move |next: &mut Client| {
+                    next.charge_back(&chargeback_tx, policy)
+                });
+
+                previous_txs.skip_after(client.apply())
+            }
+            TxType::Authorize => {
+                let amount = extx.amount.as_ref().ok_or(MissingAmountError);
+                let amount = try_on!(amount, client, previous_txs);
+                let asset = &extx.asset;
+                let client = client.prepare(move |next: &mut Client| {
+                    let balance = next.balance_mut(asset.as_ref());
+                    balance.available.sufficient_sub(amount)?;
+                    balance.held.checked_add(amount)?;
+                    Ok(())
+                });
 
-                if TxType::Deposit != chargeback_tx.ty {
-                    let err = ChargebackOnNonDepositError(txid.clone());
+                previous_txs.skip_after(client.apply())
+            }
+            TxType::Capture | TxType::Void => {
+                if let Some(ref amount) = extx.amount {
+                    let err = ExpectingEmptyAmountError(amount.clone());
                     return err!(err, client, previous_txs);
+                }
+
+                // extx and hold_tx would have the same txid information
+                let txid = &extx.txid;
+
+                let (tx_upper, hold_tx) = match previous_txs.get_mut(&extx.txid) {
+                    Ok(ok) => ok,
+                    Err(previous_txs) => {
+                        let err = HoldNotFoundError(txid.clone());
+                        return err!(err, client, previous_txs);
+                    }
                 };
-                if !chargeback_tx.is_disputed() {
-                    let err = ChargebackOnNonDisputedTxError(txid.clone());
-                    return err!(err, client, previous_txs);
+
+                if hold_tx.as_ref().ty != TxType::Authorize {
+                    let err = NonHoldError(txid.clone());
+                    return err!(err, client, tx_upper.returned(hold_tx));
+                };
+                if hold_tx.as_ref().is_settled() {
+                    let err = HoldSettledError(txid.clone());
+                    return err!(err, client, tx_upper.returned(hold_tx));
                 };
 
-                let check = client.as_ref().check_client_id(chargeback_tx);
-                try_on!(check, client, previous_txs);
+                let check = client.as_ref().check_client_id(hold_tx.as_ref());
+                try_on!(check, client, tx_upper.returned(hold_tx));
 
-                let amount = chargeback_tx.amount.as_ref().ok_or(MissingAmountError);
-                let amount = try_on!(amount, client, previous_txs).clone();
+                let amount = hold_tx.as_ref().amount.as_ref().ok_or(MissingAmountError);
+                let amount = try_on!(amount, client, tx_upper.returned(hold_tx)).clone();
+                let asset = hold_tx.as_ref().asset.clone();
+                let capture = extx.ty == TxType::Capture;
+
+                let client = client.prepare::<_, ClTxError>(|next: &mut Client| {
+                    let balance = next.balance_mut(asset.as_ref());
+                    balance.held.sufficient_sub(&amount)?;
+                    if capture {
+                        balance.total.sufficient_sub(&amount)?;
+                    } else {
+                        balance.available.checked_add(&amount)?;
+                    }
+                    Ok(())
+                });
+
+                let hold_tx = hold_tx.prepare::<_, ClTxError>(|next: &mut tx::Tx| {
+                    next.set_settled()?;
+                    Ok(())
+                });
+
+                match client.chain(hold_tx).apply() {
+                    Ok(tokens) => {
+                        let (client, tx) = tokens.split2();
+                        Ok(client.then(tx_upper.consume(tx)))
+                    }
+                    Err((e, tokens)) => {
+                        let (client, tx) = tokens.split2();
+                        err!(e, client, tx_upper.returned(tx))
+                    }
+                }
+            }
+            TxType::Transfer => {
+                // a transfer into another client is processed by
+                // `try_process_transfer` instead
+                let err = match &extx.destination {
+                    Some(destination) if destination == &client.as_ref().id => SelfTransferError,
+                    _ => MissingDestinationError,
+                };
+                err!(err, client, previous_txs)
+            }
+            TxType::Unlock | TxType::Freeze | TxType::Close | TxType::Limit => {
+                match (&extx.ty, &extx.amount) {
+                    (TxType::Limit, _) | (_, None) => (),
+                    (_, Some(amount)) => {
+                        let err = ExpectingEmptyAmountError(amount.clone());
+                        return err!(err, client, previous_txs);
+                    }
+                };
+                if extx.reason.as_deref().unwrap_or_default().is_empty() {
+                    let err = MissingReasonError;
+                    return err!(err, client, previous_txs);
+                }
+
+                if extx.ty == TxType::Limit {
+                    let limit = extx.amount.as_ref().ok_or(MissingAmountError);
+                    let limit = try_on!(limit, client, previous_txs);
+                    let client = client.prepare::<_, ClTxError>(move |next: &mut Client| {
+                        next.credit_limit = limit.clone();
+                        Ok(())
+                    });
+                    return previous_txs.skip_after(client.apply());
+                }
+
+                let status = match extx.ty {
+                    TxType::Unlock => AccountStatus::Active,
+                    TxType::Freeze => AccountStatus::Frozen,
+                    _ => {
+                        if let Some((asset, held)) = client.as_ref().held_funds() {
+                            let err = ClosingWithHeldFundsError {
+                                asset: asset.cloned(),
+                                held: held.clone(),
+                            };
+                            return err!(err, client, previous_txs);
+                        }
+                        AccountStatus::Closed
+                    }
+                };
 
                 let client = client.prepare::<_, ClTxError>(move |next: &mut Client| {
-                    next.held.sufficient_sub(&amount)?;
-                    next.total.sufficient_sub(&amount)?;
-                    next.locked = true;
+                    next.status = status;
+                    Ok(())
+                });
+
+                previous_txs.skip_after(client.apply())
+            }
+        }
+    }
+
+    /// Processes a withdrawal or a chargeback that is charged a `fee`,
+    /// which is credited into the `house` client.
+    ///
+    /// The fee is only charged if the transaction is applied. A withdrawal
+    /// is only applied if the fee can also be charged, while a chargeback
+    /// can't be refused, so its fee is capped at what the client can
+    /// afford (see `Client::affordable_fee`).
+    ///
+    /// A tx of another type is rejected (see `Policy::fee`).
+    pub fn try_process_with_fee<'t>(
+        client: TP<'t, Client>,
+        house: TP<'t, Client>,
+        extx: &'t ExternalTx,
+        previous_txs: TP<'t, Txs>,
+        policy: &Policy,
+        fee: &Amount,
+    ) -> TResult<'t, ((Client, Client), Txs), ClTxError> {
+        use ClTxError::*;
+
+        let replayed = Client::replayed(previous_txs.as_ref(), extx, policy);
+        if try_on!(
+            replayed,
+            Token::from(client).then(Token::from(house)),
+            previous_txs
+        ) {
+            // an exact resubmission is accepted without changes, nor fees
+            let clients = client.consume().then(house.consume());
+            return Ok(clients.then(previous_txs.consume()));
+        }
+
+        let check = policy.check_status(&client.as_ref().status, &extx.ty);
+        try_on!(
+            check,
+            Token::from(client).then(Token::from(house)),
+            previous_txs
+        );
+
+        // the tx reversed by a chargeback
+        let reversed = match &extx.ty {
+            TxType::Withdrawal => {
+                let amount = extx.amount.as_ref().ok_or(MissingAmountError);
+                try_on!(
+                    amount,
+                    Token::from(client).then(Token::from(house)),
+                    previous_txs
+                );
+                None
+            }
+            TxType::Chargeback => {
+                if let Some(ref amount) = extx.amount {
+                    let err = ExpectingEmptyAmountError(amount.clone());
+                    let clients = Token::from(client).then(Token::from(house));
+                    return err!(err, clients, previous_txs);
+                };
+
+                let chargeback_tx =
+                    client
+                        .as_ref()
+                        .chargeback_tx(&extx.txid, previous_txs.as_ref(), policy);
+                let chargeback_tx = try_on!(
+                    chargeback_tx,
+                    Token::from(client).then(Token::from(house)),
+                    previous_txs
+                );
+                Some(chargeback_tx.clone())
+            }
+            ty => {
+                let err = UnexpectedTxTypeError(ty.clone());
+                let clients = Token::from(client).then(Token::from(house));
+                return err!(err, clients, previous_txs);
+            }
+        };
+        let asset = match &reversed {
+            Some(tx) => tx.asset.clone(),
+            None => extx.asset.clone(),
+        };
+        let fee = match &reversed {
+            Some(tx) => client.as_ref().affordable_fee(tx, fee, policy),
+            None => fee.clone(),
+        };
+
+        let client = client.prepare::<_, ClTxError>(|next: &mut Client| {
+            match &reversed {
+                Some(tx) => next.charge_back(tx, policy)?,
+                None => {
+                    let amount = extx.amount.as_ref().ok_or(MissingAmountError)?;
+                    next.withdraw(asset.as_ref(), amount)?
+                }
+            }
+            next.pay_fee(asset.as_ref(), &fee, policy)
+        });
+
+        let house = house.prepare::<_, ClTxError>(|next: &mut Client| {
+            let balance = next.balance_mut(asset.as_ref());
+            balance.available.checked_add(&fee)?;
+            balance.total.checked_add(&fee)?;
+            Ok(())
+        });
+
+        previous_txs.skip_after(client.chain(house).apply())
+    }
+
+    /// Processes a transaction that involves two clients: a transfer
+    /// from the `source` into the `destination`, or a dispute, resolve or
+    /// chargeback of such a stored transfer.
+    ///
+    /// A transfer is disputed by its source. The dispute holds the
+    /// transferred amount on the destination, which a resolve releases,
+    /// while a chargeback returns it into the source, and locks the
+    /// destination.
+    ///
+    /// A tx of another type, which only involves one client, is rejected
+    /// (see `try_process_transaction`).
+    pub fn try_process_transfer<'t>(
+        source: TP<'t, Client>,
+        destination: TP<'t, Client>,
+        extx: &'t ExternalTx,
+        previous_txs: TP<'t, Txs>,
+        policy: &Policy,
+    ) -> TResult<'t, ((Client, Client), Txs), ClTxError> {
+        use ClTxError::*;
+
+        let replayed = Client::replayed(previous_txs.as_ref(), extx, policy);
+        if try_on!(
+            replayed,
+            Token::from(source).then(Token::from(destination)),
+            previous_txs
+        ) {
+            // an exact resubmission is accepted without changes
+            let clients = source.consume().then(destination.consume());
+            return Ok(clients.then(previous_txs.consume()));
+        }
+
+        // the destination receives a transfer as a deposit
+        let destination_ty = match &extx.ty {
+            TxType::Transfer => &TxType::Deposit,
+            ty => ty,
+        };
+        let check = policy
+            .check_status(&source.as_ref().status, &extx.ty)
+            .and_then(|()| policy.check_status(&destination.as_ref().status, destination_ty));
+        try_on!(
+            check,
+            Token::from(source).then(Token::from(destination)),
+            previous_txs
+        );
+
+        if let Some(ref amount) = extx.amount {
+            if extx.ty != TxType::Transfer {
+                let err = ExpectingEmptyAmountError(amount.clone());
+                let clients = Token::from(source).then(Token::from(destination));
+                return err!(err, clients, previous_txs);
+            }
+        }
+
+        // extx and the stored transfer would have the same txid information
+        let txid = &extx.txid;
+
+        match &extx.ty {
+            TxType::Transfer => {
+                let amount = extx.amount.as_ref().ok_or(MissingAmountError);
+                let amount = try_on!(
+                    amount,
+                    Token::from(source).then(Token::from(destination)),
+                    previous_txs
+                );
+
+                let asset = &extx.asset;
+                let source = source.prepare::<_, ClTxError>(move |next: &mut Client| {
+                    next.debit(asset.as_ref(), amount)?;
+                    Ok(())
+                });
+                let destination = destination.prepare::<_, ClTxError>(move |next: &mut Client| {
+                    let balance = next.balance_mut(asset.as_ref());
+                    balance.available.checked_add(amount)?;
+                    balance.total.checked_add(amount)?;
                     Ok(())
                 });
 
-                client.chain(previous_txs.skip()).apply()
+                // the debit is only applied together with the credit
+                previous_txs.skip_after(source.chain(destination).apply())
+            }
+            TxType::Dispute | TxType::Resolve => {
+                let disputing = extx.ty == TxType::Dispute;
+
+                let (tx_upper, stored_tx) = match previous_txs.get_mut(txid) {
+                    Ok(ok) => ok,
+                    Err(previous_txs) => {
+                        let err = if disputing {
+                            DisputationOnANotFoundTxIdError(txid.clone())
+                        } else {
+                            ResolvingOnANotFoundTxIdError(txid.clone())
+                        };
+                        let clients = Token::from(source).then(Token::from(destination));
+                        return err!(err, clients, previous_txs);
+                    }
+                };
+
+                let stored = stored_tx.as_ref();
+                let asset = stored.asset.clone();
+                let check = source
+                    .as_ref()
+                    .check_client_id(stored)
+                    .and_then(|()| destination.as_ref().check_destination(stored))
+                    .and_then(|()| stored.amount.clone().ok_or(MissingAmountError));
+                let amount = try_on!(
+                    check,
+                    Token::from(source).then(Token::from(destination)),
+                    tx_upper.returned(stored_tx)
+                );
+
+                let destination = destination.prepare::<_, ClTxError>(|next: &mut Client| {
+                    let balance = next.balance_mut(asset.as_ref());
+                    if !disputing {
+                        balance.held.sufficient_sub(&amount)?;
+                        balance.available.checked_add(&amount)?;
+                        return Ok(());
+                    }
+                    if policy.signed_balances {
+                        balance.available.checked_sub(&amount)?;
+                    } else {
+                        balance.available.sufficient_sub(&amount)?;
+                    }
+                    balance.held.checked_add(&amount)?;
+                    Ok(())
+                });
+
+                let stored_tx = stored_tx.prepare::<_, ClTxError>(|next: &mut tx::Tx| {
+                    if disputing {
+                        next.set_disputed()
+                    } else {
+                        next.unset_disputed()
+                    }
+                });
+
+                match destination.chain(stored_tx).apply() {
+                    Ok(tokens) => {
+                        let (destination, tx) = tokens.split2();
+                        let clients = source.consume().then(destination);
+                        Ok(clients.then(tx_upper.consume(tx)))
+                    }
+                    Err((e, tokens)) => {
+                        let (destination, tx) = tokens.split2();
+                        let clients = Token::from(source).then(destination);
+                        err!(e, clients, tx_upper.returned(tx))
+                    }
+                }
+            }
+            TxType::Chargeback => {
+                let stored = previous_txs
+                    .as_ref()
+                    .get(txid)
+                    .ok_or_else(|| ChargebackOnANotFoundTxIdError(txid.clone()))
+                    .and_then(|stored| {
+                        if stored.is_disputed() {
+                            Ok(stored)
+                        } else {
+                            Err(ChargebackOnNonDisputedTxError(txid.clone()))
+                        }
+                    });
+                let stored = try_on!(
+                    stored,
+                    Token::from(source).then(Token::from(destination)),
+                    previous_txs
+                );
+                let asset = stored.asset.clone();
+
+                let check = source
+                    .as_ref()
+                    .check_client_id(stored)
+                    .and_then(|()| destination.as_ref().check_destination(stored))
+                    .and_then(|()| stored.amount.clone().ok_or(MissingAmountError));
+                let amount = try_on!(
+                    check,
+                    Token::from(source).then(Token::from(destination)),
+                    previous_txs
+                );
+
+                let signed_balances = policy.signed_balances;
+                let source = source.prepare::<_, ClTxError>(|next: &mut Client| {
+                    let balance = next.balance_mut(asset.as_ref());
+                    balance.available.checked_add(&amount)?;
+                    balance.total.checked_add(&amount)?;
+                    Ok(())
+                });
+                let destination = destination.prepare::<_, ClTxError>(|next: &mut Client| {
+                    let balance = next.balance_mut(asset.as_ref());
+                    balance.held.sufficient_sub(&amount)?;
+                    if signed_balances {
+                        // which may leave a debt
+                        balance.total.checked_sub(&amount)?;
+                    } else {
+                        balance.total.sufficient_sub(&amount)?;
+                    }
+                    next.status = AccountStatus::Locked;
+                    Ok(())
+                });
+
+                previous_txs.skip_after(source.chain(destination).apply())
+            }
+            ty => {
+                let err = UnexpectedTxTypeError(ty.clone());
+                let clients = Token::from(source).then(Token::from(destination));
+                err!(err, clients, previous_txs)
             }
         }
     }
@@ -1,14 +1,14 @@
 use crate::{
     apply::token,
-    types::{client::ClTxError, Amount, ClientId},
+    policy::Precision,
+    types::{client::ClTxError, Amount, Asset, ClientId},
     TP,
 };
 use derive_more as dm;
 use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, collections::HashMap};
 use thiserror::Error;
 
-pub type Txs = OrderedTxs;
-
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum TxType {
@@ -17,6 +17,52 @@ pub enum TxType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Moves an amount from the client into a `destination` client.
+    Transfer,
+    /// Admin tx that reactivates a locked or frozen client.
+    Unlock,
+    /// Admin tx that suspends an active client.
+    Freeze,
+    /// Admin tx that definitively closes a client.
+    Close,
+    /// Holds an amount of the client, pending a capture or a void.
+    Authorize,
+    /// Debits the held amount of an authorize.
+    Capture,
+    /// Releases the held amount of an authorize.
+    Void,
+    /// Admin tx that sets the credit limit of a client to its amount.
+    Limit,
+}
+
+impl TxType {
+    /// The name used in the inputs, such as `deposit`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::Transfer => "transfer",
+            TxType::Unlock => "unlock",
+            TxType::Freeze => "freeze",
+            TxType::Close => "close",
+            TxType::Authorize => "authorize",
+            TxType::Capture => "capture",
+            TxType::Void => "void",
+            TxType::Limit => "limit",
+        }
+    }
+
+    /// Whether it's an admin tx, which changes the status (or the credit
+    /// limit) of a client and requires a reason.
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            TxType::Unlock | TxType::Freeze | TxType::Close | TxType::Limit
+        )
+    }
 }
 
 #[derive(
@@ -85,9 +131,87 @@ pub struct ExternalTx {
     #[serde(rename = "tx")]
     pub txid: TxId,
     pub amount: Option<Amount>,
+    /// The client that receives a transfer, from an optional
+    /// `destination` column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<ClientId>,
+    /// The asset of the amount, from an optional `asset` (or `currency`)
+    /// column, otherwise being the default asset.
+    #[serde(default, alias = "currency", skip_serializing_if = "Option::is_none")]
+    pub asset: Option<Asset>,
+    /// The audit reason of admin txs, from an optional `reason` column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// The unix time (in seconds) at which the hold of an authorize
+    /// expires, from an optional `expires_at` column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// The unix time (in seconds) of the tx, from an optional `timestamp`
+    /// column, which expires the holds that are due.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// The csv row, as it was read, which a replay must match (see
+    /// `Tx::is_identical`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row: Option<String>,
 }
 
 impl ExternalTx {
+    /// Checks that the amount, if any, is positive (or zero, for a
+    /// limit).
+    pub fn check_amount(&self) -> Result<(), ClTxError> {
+        match &self.amount {
+            Some(amount) if amount.is_zero() && self.ty != TxType::Limit => {
+                Err(ClTxError::ZeroAmountError)
+            }
+            Some(amount) if amount.is_negative() => {
+                Err(ClTxError::NegativeAmountError(amount.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the amount is within the `precision`, otherwise
+    /// rounding it if allowed.
+    pub fn with_precision(&self, precision: &Precision) -> Result<Cow<'_, Self>, ClTxError> {
+        let amount = match &self.amount {
+            Some(amount) if amount.decimals() > precision.decimals => amount,
+            _ => return Ok(Cow::Borrowed(self)),
+        };
+        if !precision.round {
+            return Err(ClTxError::TooManyDecimalsError {
+                amount: amount.clone(),
+                decimals: precision.decimals,
+            });
+        }
+        let mut rounded = self.clone();
+        rounded.amount = Some(amount.round(precision.decimals));
+        Ok(Cow::Owned(rounded))
+    }
+
+    /// The transaction as a csv row, such as `deposit,1,2,1.5`, where
+    /// the destination and the reason (if any) are extra fields.
+    pub fn to_row(&self) -> String {
+        let mut row = format!(
+            "{},{},{},{}",
+            self.ty.as_str(),
+            u16::from(self.client.clone()),
+            u32::from(self.txid.clone()),
+            self.amount
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        );
+        if let Some(destination) = &self.destination {
+            row.push_str(&format!(",{}", u16::from(destination.clone())));
+        }
+        if let Some(reason) = &self.reason {
+            row.push(',');
+            row.push_str(reason);
+        }
+        row
+    }
+
     pub fn client_error(&self, error: ClTxError, internal_txid: InternalTxId) -> TxError {
         TxError {
             txid: self.txid.clone(),
@@ -97,14 +221,32 @@ impl ExternalTx {
     }
 }
 
-#[derive(Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Tx {
     pub ty: TxType,
     pub client: ClientId,
     pub txid: TxId,
     pub internal_txid: InternalTxId,
     pub amount: Option<Amount>,
+    /// The client that received a transfer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<ClientId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<Asset>,
+    /// When the hold of an authorize expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Kept so a replay is only accepted if it's identical.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row: Option<String>,
     disputed: bool,
+    /// Whether the hold of an authorize was captured, voided or expired.
+    #[serde(default)]
+    settled: bool,
 }
 
 impl Tx {
@@ -129,6 +271,17 @@ impl Tx {
             Ok(())
         }
     }
+    pub fn is_settled(&self) -> bool {
+        self.settled
+    }
+    pub fn set_settled(&mut self) -> Result<(), ClTxError> {
+        if self.settled {
+            Err(ClTxError::HoldSettledError(self.txid.clone()))
+        } else {
+            self.settled = true;
+            Ok(())
+        }
+    }
     pub fn from_external(external: &ExternalTx, internal_txid: InternalTxId) -> Self {
         Self {
             ty: external.ty.clone(),
@@ -136,9 +289,41 @@ impl Tx {
             txid: external.txid.clone(),
             internal_txid,
             amount: external.amount.clone(),
+            destination: external.destination.clone(),
+            asset: external.asset.clone(),
+            expires_at: external.expires_at,
+            reason: external.reason.clone(),
+            timestamp: external.timestamp,
+            row: external.row.clone(),
             disputed: false,
+            settled: false,
         }
     }
+    /// Whether the external tx is an exact resubmission of this one.
+    ///
+    /// The amounts must also be written with the same decimals, so an
+    /// amount of `1.0` is not the same as `1.00`, and the csv rows, when
+    /// both were read from one, must be the same.
+    pub fn is_identical(&self, external: &ExternalTx) -> bool {
+        let same_amount = match (&self.amount, &external.amount) {
+            (Some(stored), Some(amount)) => stored == amount && stored.scale() == amount.scale(),
+            (stored, amount) => stored == amount,
+        };
+        let same_row = match (&self.row, &external.row) {
+            (Some(stored), Some(row)) => stored == row,
+            _ => true,
+        };
+        self.ty == external.ty
+            && self.client == external.client
+            && self.txid == external.txid
+            && same_amount
+            && self.destination == external.destination
+            && self.asset == external.asset
+            && self.expires_at == external.expires_at
+            && self.reason == external.reason
+            && self.timestamp == external.timestamp
+            && same_row
+    }
     pub fn check_client_id(&self, client_id: &ClientId) -> Result<(), ClTxError> {
         if &self.client == client_id {
             Ok(())
@@ -151,7 +336,7 @@ impl Tx {
     }
 }
 
-#[derive(Debug, Error)]
+#[derive(Clone, Debug, PartialEq, Eq, Error, Serialize)]
 #[error("Incoming tx {txid:?}, internal id {internal_txid:?}. error: {error}")]
 pub struct TxError {
     txid: TxId,
@@ -159,28 +344,73 @@ pub struct TxError {
     error: ClTxError,
 }
 
-#[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Ord, PartialOrd, dm::From, dm::Into)]
-pub struct OrderedTxs(Vec<Tx>);
+impl TxError {
+    pub fn txid(&self) -> &TxId {
+        &self.txid
+    }
+    pub fn internal_txid(&self) -> &InternalTxId {
+        &self.internal_txid
+    }
+    pub fn error(&self) -> &ClTxError {
+        &self.error
+    }
+    pub fn into_error(self) -> ClTxError {
+        self.error
+    }
+    /// See `ClTxError::code`.
+    pub fn code(&self) -> &'static str {
+        self.error.code()
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Error)]
+#[error("The tx id {0:?} is already stored")]
+pub struct DuplicateTxIdError(pub TxId);
+
+/// The stored transactions, indexed by their `TxId`.
+///
+/// The ids are not required to be ordered, only unique.
+#[derive(Clone, Debug, Default, Eq, PartialEq, dm::From, dm::Into)]
+pub struct Txs(HashMap<TxId, Tx>);
 
-impl OrderedTxs {
-    pub fn push_ordered(&mut self, client_tx: Tx) {
-        if let Some(last_id) = self.0.last().map(|cltx| &cltx.txid) {
-            // a drafty way to guarantee that it's ordered
-            assert!(last_id < &client_tx.txid);
+impl Txs {
+    /// Stores a new `Tx`.
+    ///
+    /// The `Tx` is rejected if another one with the same id is already
+    /// stored, in which case the stored one is kept unchanged.
+    pub fn insert(&mut self, client_tx: Tx) -> Result<(), DuplicateTxIdError> {
+        use std::collections::hash_map::Entry;
+        match self.0.entry(client_tx.txid.clone()) {
+            Entry::Occupied(_) => Err(DuplicateTxIdError(client_tx.txid)),
+            Entry::Vacant(entry) => {
+                entry.insert(client_tx);
+                Ok(())
+            }
         }
-        self.0.push(client_tx);
     }
 
     pub fn get(&self, tx: &TxId) -> Option<&Tx> {
-        self.0
-            // assumes the vec is ordered
-            .binary_search_by_key(tx, |cltx| cltx.txid.clone())
-            .ok()
-            .and_then(|index| self.0.get(index))
+        self.0.get(tx)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Tx> {
+        self.0.values()
+    }
+
+    pub fn contains(&self, tx: &TxId) -> bool {
+        self.0.contains_key(tx)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 }
 
-impl<'t> TP<'t, OrderedTxs> {
+impl<'t> TP<'t, Txs> {
     /// Gets a protected `Tx` from the `Txs`,
     /// and also a Token upgrader (from `Tx` into `Txs`).
     ///
@@ -188,24 +418,19 @@ impl<'t> TP<'t, OrderedTxs> {
     pub fn get_mut<'l>(
         self,
         tx: &TxId,
-    ) -> Result<(token::UpgraderToken<'t, 'l, OrderedTxs, Tx>, TP<'l, Tx>), Self>
+    ) -> Result<(token::UpgraderToken<'t, 'l, Txs, Tx>, TP<'l, Tx>), Self>
     where
         't: 'l,
     {
-        let index = match self
-            .as_ref()
-            .0 // assumes the vec is ordered
-            .binary_search_by_key(tx, |cltx| cltx.txid.clone())
-        {
-            Ok(index) => index,
-            Err(_) => return Err(self),
-        };
+        if !self.as_ref().contains(tx) {
+            return Err(self);
+        }
 
-        let access = |txs: &'t mut OrderedTxs| {
+        let access = |txs: &'t mut Txs| {
             // Safety:
             //
-            // the index must be a valid one.
-            txs.0.get_mut(index).unwrap()
+            // the tx must be a stored one.
+            txs.0.get_mut(tx).unwrap()
         };
 
         // Safety:
@@ -0,0 +1,58 @@
+use payment_engine::{tx::TxType, Engine, ExternalTx, Policy};
+use rust_decimal::Decimal;
+
+const INPUT: &str = "type, client, tx, amount
+deposit, 1, 1, 5.0
+deposit, 1, 2, -5.0
+withdrawal, 1, 3, -1.0
+deposit, 1, 4, 0
+withdrawal, 1, 5, 0.0
+dispute, 1, 2,
+";
+
+#[test]
+fn amounts_non_positive() {
+    let mut codes = vec![];
+    let rows = payment_engine::read_input(INPUT.as_bytes());
+    let clients = payment_engine::run_rows(rows, &Policy::default(), |reject| {
+        codes.push(reject.code);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(
+        codes,
+        vec![
+            "E_NEGATIVE_AMOUNT",
+            "E_NEGATIVE_AMOUNT",
+            "E_ZERO_AMOUNT",
+            "E_ZERO_AMOUNT",
+            // the negative deposit was not stored
+            "E_DISPUTE_TX_NOT_FOUND",
+        ]
+    );
+    assert_eq!(clients[&1.into()].total, Decimal::new(5, 0).into());
+}
+
+#[test]
+fn amounts_overflow() {
+    let max = Decimal::from_parts(u32::MAX, u32::MAX, u32::MAX, false, 0);
+    let deposit = |txid: u32| ExternalTx {
+        ty: TxType::Deposit,
+        client: 1.into(),
+        txid: txid.into(),
+        amount: Some(max.into()),
+        destination: None,
+        asset: None,
+        reason: None,
+        expires_at: None,
+        timestamp: None,
+        row: None,
+    };
+    let mut engine = Engine::default();
+    engine.process(&deposit(1)).unwrap();
+    let e = engine.process(&deposit(2)).unwrap_err();
+    assert_eq!(e.code(), "E_AMOUNT_OVERFLOW");
+    // the client is unchanged
+    assert_eq!(engine.client(&1.into()).unwrap().total, max.into());
+    assert!(engine.tx(&2.into()).is_none());
+}
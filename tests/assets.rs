@@ -0,0 +1,93 @@
+use payment_engine::{client::ClTxError, types::Asset, Engine, Policy};
+
+mod common;
+use common::{asset_tx as tx, engine_with};
+
+fn output(engine: Engine) -> String {
+    let mut clients: Vec<_> = engine.finish().into_values().collect();
+    clients.sort_by(|a, b| a.id.cmp(&b.id));
+    let mut out = vec![];
+    payment_engine::write_output(clients.into_iter(), &mut out).unwrap();
+    String::from_utf8(out).unwrap().replace("\r\n", "\n")
+}
+
+#[test]
+fn assets_separate_balances() {
+    let mut engine = engine_with(
+        Policy::default(),
+        &[
+            tx("deposit, 1, 1, 10, EUR"),
+            tx("deposit, 1, 2, 2, BTC"),
+            tx("withdrawal, 1, 3, 3, EUR"),
+        ],
+    );
+    // no funds on the other assets
+    let e = engine.process(&tx("withdrawal, 1, 4, 3, BTC")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+    let e = engine.process(&tx("withdrawal, 1, 5, 1,")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+
+    let client = engine.client(&1.into()).unwrap();
+    let eur = &client.assets[&Asset::from("EUR".to_string())];
+    assert_eq!(eur.available.to_string(), "7");
+    assert_eq!(client.total.to_string(), "0");
+    assert_eq!(
+        output(engine),
+        "client,asset,available,held,total,locked\n\
+         1,BTC,2,0,2,false\n\
+         1,EUR,7,0,7,false\n"
+    );
+}
+
+#[test]
+fn assets_disputes() {
+    let mut engine = engine_with(
+        Policy::default(),
+        &[
+            tx("deposit, 1, 1, 10,"),
+            tx("deposit, 1, 2, 4, EUR"),
+            tx("dispute, 1, 2,,"),
+        ],
+    );
+    // the dispute holds the amount on the asset of the disputed tx
+    let client = engine.client(&1.into()).unwrap();
+    let eur = &client.assets[&Asset::from("EUR".to_string())];
+    assert_eq!(eur.held.to_string(), "4");
+    assert_eq!(client.held.to_string(), "0");
+
+    engine.process(&tx("chargeback, 1, 2,,")).unwrap();
+    assert_eq!(
+        output(engine),
+        "client,asset,available,held,total,locked\n\
+         1,,10,0,10,true\n\
+         1,EUR,0,0,0,true\n"
+    );
+}
+
+#[test]
+fn assets_default_output() {
+    let engine = engine_with(Policy::default(), &[tx("deposit, 1, 1, 10,")]);
+    assert_eq!(
+        output(engine),
+        "client,available,held,total,locked\n\
+         1,10,0,10,false\n"
+    );
+}
+
+#[test]
+fn assets_close_held() {
+    let mut engine = engine_with(
+        Policy::default(),
+        &[tx("deposit, 1, 1, 10, EUR"), tx("dispute, 1, 1,,")],
+    );
+    let mut close = tx("close, 1, 2,,");
+    close.reason = Some("done".to_string());
+    let e = engine.process(&close).unwrap_err();
+    assert_eq!(
+        e.into_error(),
+        ClTxError::ClosingWithHeldFundsError {
+            asset: Some(Asset::from("EUR".to_string())),
+            held: rust_decimal::Decimal::new(10, 0).into(),
+        }
+    );
+}
@@ -1,9 +1,15 @@
+use payment_engine::Client;
+use rust_decimal::Decimal;
 use std::path::PathBuf;
 
 fn run(path: &str, expected: &str) {
-    let path = PathBuf::from(path);
-    let inputs = payment_engine::read_input_file(&path).unwrap();
-    let clients = payment_engine::run(inputs.into_iter());
+    run_many(&[path], expected)
+}
+
+fn run_many(paths: &[&str], expected: &str) {
+    let paths = paths.iter().map(PathBuf::from);
+    let inputs = payment_engine::read_input_files(paths).unwrap();
+    let clients = payment_engine::run_rows(inputs, &Default::default(), |_reject| Ok(())).unwrap();
     let mut clients: Vec<_> = clients.values().collect();
     clients.sort_by(|a, b| a.id.cmp(&b.id));
     let mut output = Vec::new();
@@ -66,3 +72,174 @@ fn basic_chargeback() {
     2,0,0,0,true",
     );
 }
+
+#[test]
+fn basic_malformed() {
+    let path = PathBuf::from("tests/basic_malformed.csv");
+    let inputs = payment_engine::read_input_file(&path).unwrap();
+    let mut rejects = vec![];
+    let clients = payment_engine::run_rows(inputs, &Default::default(), |reject| {
+        rejects.push(reject);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(clients.len(), 1);
+    assert_eq!(rejects.len(), 1);
+    assert_eq!(rejects[0].line, 3);
+    assert_eq!(rejects[0].code, payment_engine::Reject::MALFORMED_ROW);
+    assert_eq!(rejects[0].row, "deposit, two, 2, 1.0");
+}
+
+#[test]
+fn basic_malformed_original_row() {
+    let input: &[u8] = b"type, client, tx, amount\ndeposit, 1, \"2, 1.0\ndeposit, 1, 3,\xff 1.0\r\ndeposit, 1, 4, 1.0\n";
+    let mut rejects = vec![];
+    let clients = payment_engine::run_rows(
+        payment_engine::read_input(input),
+        &Default::default(),
+        |reject| {
+            rejects.push((reject.line, reject.code, reject.row));
+            Ok(())
+        },
+    )
+    .unwrap();
+    assert_eq!(clients.len(), 1);
+    assert_eq!(
+        rejects,
+        vec![
+            (
+                2,
+                payment_engine::Reject::MALFORMED_ROW,
+                "deposit, 1, \"2, 1.0".into()
+            ),
+            (
+                3,
+                payment_engine::Reject::MALFORMED_ROW,
+                "deposit, 1, 3,\u{fffd} 1.0".into()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn basic_rejects() {
+    let path = PathBuf::from("tests/basic_rejects.csv");
+    let inputs = payment_engine::read_input_file(&path).unwrap();
+    let mut output = Vec::new();
+    let mut writer = payment_engine::reject::RejectWriter::new(&mut output);
+    let mut rejects = vec![];
+    payment_engine::run_rows(inputs, &Default::default(), |reject| {
+        writer.write(&reject)?;
+        rejects.push((
+            reject.line,
+            reject.internal_txid.into(),
+            reject.code,
+            reject.row,
+        ));
+        Ok(())
+    })
+    .unwrap();
+    writer.flush().unwrap();
+    drop(writer);
+
+    let rejects: Vec<(u64, u32, &str, String)> = rejects;
+    assert_eq!(
+        rejects,
+        vec![
+            (3, 1, "E_INSUFFICIENT_FUNDS", "withdrawal, 2, 2, 2.0".into()),
+            (4, 2, "E_MALFORMED_ROW", "dispute, 2".into()),
+            (5, 3, "E_DISPUTE_TX_NOT_FOUND", "dispute, 2, 9,".into()),
+        ]
+    );
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.lines().count(), 4);
+    assert!(output.starts_with("line,internal_tx,code,reason,row"));
+}
+
+#[test]
+fn basic_rejects_appending() {
+    let path = PathBuf::from("tests/basic_rejects.csv");
+    let inputs = payment_engine::read_input_file(&path).unwrap();
+    let mut output = Vec::new();
+    let mut writer = payment_engine::reject::RejectWriter::appending(&mut output);
+    payment_engine::run_rows(inputs, &Default::default(), |reject| writer.write(&reject)).unwrap();
+    writer.flush().unwrap();
+    drop(writer);
+
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.lines().count(), 3);
+    assert!(output.starts_with("3,1,E_INSUFFICIENT_FUNDS"));
+}
+
+#[test]
+fn multi_files() {
+    run_many(
+        &["tests/multi_day1.csv", "tests/multi_day2.csv"],
+        "client,available,held,total,locked
+    1,1.5,0,1.5,false
+    2,0,1,1,false",
+    );
+}
+
+#[test]
+fn multi_missing_file() {
+    let paths = ["tests/multi_day1.csv", "tests/missing.csv"];
+    assert!(payment_engine::read_input_files(paths.iter()).is_err());
+}
+
+#[test]
+fn basic_unordered() {
+    run(
+        "tests/basic_unordered.csv",
+        "client,available,held,total,locked
+    2,0.5,2,2.5,false",
+    );
+}
+
+fn run_rejects(
+    path: &str,
+    policy: &payment_engine::Policy,
+) -> (Vec<Client>, Vec<(u64, &'static str)>) {
+    let inputs = payment_engine::read_input_file(&PathBuf::from(path)).unwrap();
+    let mut rejects = vec![];
+    let clients = payment_engine::run_rows(inputs, policy, |reject| {
+        rejects.push((reject.line, reject.code));
+        Ok(())
+    })
+    .unwrap();
+    let mut clients: Vec<_> = clients.values().cloned().collect();
+    clients.sort_by(|a, b| a.id.cmp(&b.id));
+    (clients, rejects)
+}
+
+#[test]
+fn basic_duplicates() {
+    let policy = payment_engine::Policy::default();
+    let (clients, rejects) = run_rejects("tests/basic_duplicates.csv", &policy);
+    // client 2 is still created, even though its only tx was rejected
+    assert_eq!(clients.len(), 2);
+    assert_eq!(clients[0].total, Decimal::new(5, 1).into());
+    assert_eq!(
+        rejects,
+        vec![
+            (3, "E_DUPLICATE_TX"),
+            (4, "E_DUPLICATE_TX"),
+            (6, "E_DUPLICATE_TX"),
+            (7, "E_DUPLICATE_TX"),
+        ]
+    );
+}
+
+#[test]
+fn basic_idempotent_replays() {
+    let policy = payment_engine::Policy {
+        idempotent_replays: true,
+        ..Default::default()
+    };
+    let (clients, rejects) = run_rejects("tests/basic_duplicates.csv", &policy);
+    // client 2 is still created, even though its only tx was rejected
+    assert_eq!(clients.len(), 2);
+    assert_eq!(clients[0].total, Decimal::new(5, 1).into());
+    // not identical: different amount, and different client
+    assert_eq!(rejects, vec![(4, "E_DUPLICATE_TX"), (7, "E_DUPLICATE_TX")]);
+}
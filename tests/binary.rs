@@ -0,0 +1,93 @@
+use payment_engine::{binary, Policy};
+
+const CSV: &str = "type, client, tx, amount
+deposit, 1, 1, 2.0
+withdrawal, 1, 2, 0.5
+deposit, 2, 3, 1.1234
+dispute, 2, 3,
+chargeback, 2, 3,
+unlock, 2, 4,
+";
+
+fn encode(csv: &str) -> Vec<u8> {
+    let mut bin = vec![];
+    let written = binary::convert(payment_engine::read_input(csv.as_bytes()), &mut bin).unwrap();
+    assert_eq!(written, csv.lines().count() as u64 - 1);
+    bin
+}
+
+#[test]
+fn binary_same_as_csv() {
+    let bin = encode(CSV);
+    // magic, 3 records with amounts and 3 without, each with a length
+    assert_eq!(bin.len(), 4 + 3 * 16 + 3 * 8);
+
+    let txs: Vec<_> = payment_engine::read_input(CSV.as_bytes())
+        .map(|row| {
+            let mut tx = row.unwrap().tx.unwrap();
+            // only kept from csv rows
+            tx.row = None;
+            tx
+        })
+        .collect();
+    let decoded: Vec<_> = binary::read_txs(bin.as_slice())
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(txs, decoded);
+
+    let policy = Policy::default();
+    let rows = payment_engine::read_input(CSV.as_bytes());
+    let from_csv = payment_engine::run_rows(rows, &policy, |_| Ok(())).unwrap();
+    let rows = binary::read_input(bin.as_slice());
+    let from_bin = payment_engine::run_rows(rows, &policy, |_| Ok(())).unwrap();
+    assert_eq!(from_csv, from_bin);
+    let from_txs = payment_engine::try_run(binary::read_txs(bin.as_slice())).unwrap();
+    assert_eq!(from_csv, from_txs);
+
+    // the reason of an admin tx is kept
+    let csv = "type, client, tx, amount, reason\nfreeze, 1, 1,, fraud review\n";
+    let bin = encode(csv);
+    let decoded = binary::read_txs(bin.as_slice()).next().unwrap().unwrap();
+    assert_eq!(decoded.reason.as_deref(), Some("fraud review"));
+
+    // and the destination of a transfer
+    let csv = "type, client, tx, amount, destination\ntransfer, 1, 2, 1.5, 3\n";
+    let bin = encode(csv);
+    let decoded = binary::read_txs(bin.as_slice()).next().unwrap().unwrap();
+    assert_eq!(decoded.destination, Some(3.into()));
+    assert_eq!(decoded.amount.unwrap().to_string(), "1.5");
+
+    // and both the amount and the reason of a limit
+    let csv = "type, client, tx, amount, reason\nlimit, 1, 3, 250, approved\n";
+    let bin = encode(csv);
+    let decoded = binary::read_txs(bin.as_slice()).next().unwrap().unwrap();
+    assert_eq!(decoded.amount.unwrap().to_string(), "250");
+    assert_eq!(decoded.reason.as_deref(), Some("approved"));
+}
+
+#[test]
+fn binary_errors() {
+    // more decimals than the encoding supports
+    let mut bin = vec![];
+    let csv = "type, client, tx, amount\ndeposit, 1, 1, 0.00001\n";
+    let e = binary::convert(payment_engine::read_input(csv.as_bytes()), &mut bin).unwrap_err();
+    assert!(e.to_string().starts_with("Line 2: "));
+
+    // an unknown tag is a malformed row, which can be skipped
+    let mut bin = encode("type, client, tx, amount\ndeposit, 1, 1, 1.0\ndeposit, 1, 2, 1.0\n");
+    bin[5] = 200;
+    let rows: Vec<_> = binary::read_input(bin.as_slice())
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(rows.len(), 2);
+    assert!(rows[0].tx.is_err());
+    assert_eq!(rows[1].raw(), "deposit,1,2,1");
+
+    // a truncated record stops the input
+    let bin = encode("type, client, tx, amount\ndeposit, 1, 1, 1.0\n");
+    let results: Vec<_> = binary::read_input(&bin[..bin.len() - 1]).collect();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_err());
+
+    assert!(binary::read_txs(&b"nope"[..]).next().unwrap().is_err());
+}
@@ -0,0 +1,109 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use payment_engine::{Engine, ExternalTx, Policy};
+
+/// The tx of a single csv `row` that has the `type, client, tx, amount`
+/// columns.
+pub fn tx(row: &str) -> ExternalTx {
+    tx_with("type, client, tx, amount", row)
+}
+
+/// The tx of a single csv `row` that has the columns of the `header`.
+pub fn tx_with(header: &str, row: &str) -> ExternalTx {
+    let input = format!("{}\n{}\n", header, row);
+    let row = payment_engine::read_input(input.as_bytes())
+        .next()
+        .unwrap()
+        .unwrap();
+    row.tx.unwrap()
+}
+
+/// The tx of a single csv `row` that also has a `reason` column.
+pub fn reason_tx(row: &str) -> ExternalTx {
+    tx_with("type, client, tx, amount, reason", row)
+}
+
+/// The tx of a single csv `row` that also has a `destination` column.
+pub fn transfer_tx(row: &str) -> ExternalTx {
+    tx_with("type, client, tx, amount, destination", row)
+}
+
+/// The tx of a single csv `row` that also has an `asset` column.
+pub fn asset_tx(row: &str) -> ExternalTx {
+    tx_with("type, client, tx, amount, asset", row)
+}
+
+/// The tx of a single csv `row` that also has the `expires_at,
+/// timestamp` columns.
+pub fn hold_tx(row: &str) -> ExternalTx {
+    tx_with("type, client, tx, amount, expires_at, timestamp", row)
+}
+
+/// An engine with the `policy` that already accepted the `txs`.
+pub fn engine_with(policy: Policy, txs: &[ExternalTx]) -> Engine {
+    let mut engine = Engine::new(policy);
+    for tx in txs {
+        engine.process(tx).unwrap();
+    }
+    engine
+}
+
+/// The (available, held, total) of a client.
+pub fn balances(engine: &Engine, client: u16) -> (String, String, String) {
+    let client = engine.client(&client.into()).unwrap();
+    (
+        client.available.to_string(),
+        client.held.to_string(),
+        client.total.to_string(),
+    )
+}
+
+pub fn b(available: &str, held: &str, total: &str) -> (String, String, String) {
+    (available.into(), held.into(), total.into())
+}
+
+/// Generates a deterministic mix of transactions for many clients, as
+/// csv.
+pub fn generate_csv(len: u32, clients: u16) -> String {
+    let mut seed: u64 = 42;
+    let mut next = move |max: u64| {
+        // a simple linear congruential generator
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+        (seed >> 33) % max
+    };
+    let mut csv = String::from("type, client, tx, amount\n");
+    for txid in 1..=len {
+        let client = next(clients as u64);
+        let row = match next(10) {
+            // a tx id may be reused, also by another client
+            0 => format!(
+                "deposit, {}, {}, {}.{:02}",
+                client,
+                next(txid as u64),
+                next(100),
+                next(100)
+            ),
+            1..=4 => format!(
+                "deposit, {}, {}, {}.{:02}",
+                client,
+                txid,
+                next(100),
+                next(100)
+            ),
+            5..=6 => format!(
+                "withdrawal, {}, {}, {}.{:02}",
+                client,
+                txid,
+                next(100),
+                next(100)
+            ),
+            7 => format!("dispute, {}, {},", client, next(txid as u64)),
+            8 => format!("resolve, {}, {},", client, next(txid as u64)),
+            _ => format!("chargeback, {}, {},", client, next(txid as u64)),
+        };
+        csv.push_str(&row);
+        csv.push('\n');
+    }
+    csv
+}
@@ -0,0 +1,67 @@
+use payment_engine::{Engine, Policy};
+
+mod common;
+use common::{b, balances, reason_tx as tx};
+
+fn credit_limits() -> Policy {
+    let mut policy = Policy::default();
+    policy
+        .load_credit_limits("tests/credit_limits.csv")
+        .unwrap();
+    policy
+}
+
+#[test]
+fn credit_withdrawal() {
+    let mut engine = Engine::new(credit_limits());
+    engine.process(&tx("deposit, 1, 1, 10,")).unwrap();
+    engine.process(&tx("withdrawal, 1, 2, 60,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("-50", "0", "-50"));
+    engine.process(&tx("withdrawal, 1, 3, 50,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("-100", "0", "-100"));
+
+    let e = engine.process(&tx("withdrawal, 1, 4, 0.01,")).unwrap_err();
+    assert_eq!(e.code(), "E_CREDIT_LIMIT_EXCEEDED");
+    assert_eq!(balances(&engine, 1), b("-100", "0", "-100"));
+
+    // without a limit, the floor is still zero
+    engine.process(&tx("deposit, 3, 5, 10,")).unwrap();
+    let e = engine.process(&tx("withdrawal, 3, 6, 11,")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+}
+
+#[test]
+fn credit_limit_tx() {
+    let mut engine = Engine::new(credit_limits());
+    let e = engine.process(&tx("limit, 3, 1, 20,")).unwrap_err();
+    assert_eq!(e.code(), "E_MISSING_REASON");
+    let e = engine
+        .process(&tx("limit, 3, 1, -20, approved"))
+        .unwrap_err();
+    assert_eq!(e.code(), "E_NEGATIVE_AMOUNT");
+    engine.process(&tx("limit, 3, 1, 20, approved")).unwrap();
+    engine.process(&tx("withdrawal, 3, 2, 20,")).unwrap();
+    assert_eq!(balances(&engine, 3), b("-20", "0", "-20"));
+
+    // a zero limit only prevents further debits
+    engine.process(&tx("limit, 3, 3, 0, revoked")).unwrap();
+    let e = engine.process(&tx("withdrawal, 3, 4, 1,")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+}
+
+#[test]
+fn credit_output() {
+    let mut engine = Engine::new(credit_limits());
+    engine.process(&tx("withdrawal, 2, 1, 50,")).unwrap();
+    engine.process(&tx("deposit, 3, 2, 1,")).unwrap();
+    let mut clients: Vec<_> = engine.finish().into_values().collect();
+    clients.sort_by(|a, b| a.id.cmp(&b.id));
+    let mut out = vec![];
+    payment_engine::write_output(clients.into_iter(), &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap().replace("\r\n", "\n"),
+        "client,available,held,total,locked,credit_limit,debt\n\
+         2,-50,0,-50,false,50.5,50\n\
+         3,1,0,1,false,0,0\n"
+    );
+}
@@ -0,0 +1,105 @@
+use payment_engine::{AccountStatus, Engine, Policy};
+
+mod common;
+use common::{engine_with, tx};
+
+/// The (available, held, total, locked) of the client, as rendered on
+/// the output.
+fn balances(engine: &Engine) -> (String, String, String, bool) {
+    let client = engine.client(&1.into()).unwrap();
+    (
+        client.available.to_string(),
+        client.held.to_string(),
+        client.total.to_string(),
+        client.status != AccountStatus::Active,
+    )
+}
+
+fn withdrawal_disputes() -> Policy {
+    Policy {
+        withdrawal_disputes: true,
+        ..Default::default()
+    }
+}
+
+fn b(available: &str, held: &str, total: &str, locked: bool) -> (String, String, String, bool) {
+    (available.into(), held.into(), total.into(), locked)
+}
+
+#[test]
+fn disputes_withdrawal_not_allowed() {
+    let mut engine = Engine::default();
+    engine.process(&tx("deposit, 1, 1, 10")).unwrap();
+    engine.process(&tx("withdrawal, 1, 2, 4")).unwrap();
+    let e = engine.process(&tx("dispute, 1, 2,")).unwrap_err();
+    assert_eq!(e.code(), "E_DISPUTE_NON_DEPOSIT");
+    assert_eq!(balances(&engine), b("6", "0", "6", false));
+}
+
+#[test]
+fn disputes_withdrawal_resolve() {
+    let mut engine = engine_with(
+        withdrawal_disputes(),
+        &[tx("deposit, 1, 1, 10"), tx("withdrawal, 1, 2, 4")],
+    );
+
+    // the withdrawn funds are returned into held
+    engine.process(&tx("dispute, 1, 2,")).unwrap();
+    assert_eq!(balances(&engine), b("6", "4", "10", false));
+
+    // the withdrawal stands, and is re-debited
+    engine.process(&tx("resolve, 1, 2,")).unwrap();
+    assert_eq!(balances(&engine), b("6", "0", "6", false));
+    assert!(!engine.tx(&2.into()).unwrap().is_disputed());
+}
+
+#[test]
+fn disputes_withdrawal_chargeback() {
+    let mut engine = engine_with(
+        withdrawal_disputes(),
+        &[tx("deposit, 1, 1, 10"), tx("withdrawal, 1, 2, 4")],
+    );
+    engine.process(&tx("dispute, 1, 2,")).unwrap();
+
+    // the withdrawal is reversed, and credited back
+    engine.process(&tx("chargeback, 1, 2,")).unwrap();
+    assert_eq!(balances(&engine), b("10", "0", "10", true));
+}
+
+#[test]
+fn disputes_withdrawal_policy_changed() {
+    let mut engine = engine_with(
+        withdrawal_disputes(),
+        &[tx("deposit, 1, 1, 10"), tx("withdrawal, 1, 2, 4")],
+    );
+    engine.process(&tx("dispute, 1, 2,")).unwrap();
+
+    // restarted without withdrawal disputes, the dispute can't be settled
+    let mut engine = Engine::from_snapshot(engine.snapshot(), Policy::default()).unwrap();
+    let e = engine.process(&tx("resolve, 1, 2,")).unwrap_err();
+    assert_eq!(e.code(), "E_RESOLVE_NON_DEPOSIT");
+    let e = engine.process(&tx("chargeback, 1, 2,")).unwrap_err();
+    assert_eq!(e.code(), "E_CHARGEBACK_NON_DEPOSIT");
+    assert_eq!(balances(&engine), b("6", "4", "10", false));
+}
+
+#[test]
+fn disputes_deposit_unchanged() {
+    let mut engine = engine_with(
+        withdrawal_disputes(),
+        &[tx("deposit, 1, 1, 10"), tx("withdrawal, 1, 2, 4")],
+    );
+
+    // the deposit can't be disputed, as some of it was withdrawn
+    let e = engine.process(&tx("dispute, 1, 1,")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+
+    engine.process(&tx("deposit, 1, 3, 2")).unwrap();
+    engine.process(&tx("dispute, 1, 3,")).unwrap();
+    engine.process(&tx("dispute, 1, 2,")).unwrap();
+    assert_eq!(balances(&engine), b("6", "6", "12", false));
+
+    engine.process(&tx("chargeback, 1, 3,")).unwrap();
+    engine.process(&tx("resolve, 1, 2,")).unwrap();
+    assert_eq!(balances(&engine), b("6", "0", "6", true));
+}
@@ -0,0 +1,78 @@
+use payment_engine::{client::ClTxError, Engine, Outcome, Policy};
+
+mod common;
+use common::{reason_tx, tx};
+
+/// The value as it would be rendered on the output.
+fn rendered(value: &impl serde::Serialize) -> String {
+    match serde_json::to_value(value).unwrap() {
+        serde_json::Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+#[test]
+fn engine_incremental() {
+    let mut engine = Engine::default();
+    assert!(engine.client(&1.into()).is_none());
+
+    let outcome = engine.process(&tx("deposit, 1, 1, 10.0")).unwrap();
+    assert_eq!(outcome, Outcome::Applied(0.into()));
+    let client = engine.client(&1.into()).unwrap();
+    assert_eq!(rendered(&client.available), "10");
+    assert_eq!(rendered(&client.held), "0");
+
+    engine.process(&tx("dispute, 1, 1,")).unwrap();
+    assert!(engine.tx(&1.into()).unwrap().is_disputed());
+    let client = engine.client(&1.into()).unwrap();
+    assert_eq!(rendered(&client.available), "0");
+    assert_eq!(rendered(&client.held), "10");
+
+    engine.process(&tx("resolve, 1, 1,")).unwrap();
+    assert!(!engine.tx(&1.into()).unwrap().is_disputed());
+
+    // disputes are not stored as txs
+    assert!(engine.tx(&2.into()).is_none());
+    assert_eq!(engine.next_internal_txid(), &3.into());
+
+    let clients = engine.finish();
+    assert_eq!(clients.len(), 1);
+    assert_eq!(rendered(&clients[&1.into()].total), "10");
+}
+
+#[test]
+fn engine_errors() {
+    let mut engine = Engine::default();
+    engine.process(&tx("deposit, 1, 1, 1.0")).unwrap();
+
+    let e = engine.process(&tx("withdrawal, 1, 2, 5.0")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+    assert_eq!(e.internal_txid(), &1.into());
+    // the failed withdrawal is not stored
+    assert!(engine.tx(&2.into()).is_none());
+
+    let e = engine.process(&tx("deposit, 1, 1, 1.0")).unwrap_err();
+    assert_eq!(e.into_error(), ClTxError::DuplicateTxIdError(1.into()));
+    assert_eq!(rendered(&engine.client(&1.into()).unwrap().total), "1");
+}
+
+#[test]
+fn engine_replays() {
+    let mut engine = Engine::new(Policy {
+        idempotent_replays: true,
+        ..Default::default()
+    });
+    engine.process(&tx("deposit, 1, 1, 1.0")).unwrap();
+    let outcome = engine.process(&tx("deposit, 1, 1, 1.0")).unwrap();
+    assert_eq!(outcome, Outcome::Replayed(1.into()));
+    // the amounts are compared as written
+    let e = engine.process(&tx("deposit, 1, 1, 1.00")).unwrap_err();
+    assert_eq!(e.code(), "E_DUPLICATE_TX");
+    let e = engine
+        .process(&reason_tx("deposit, 1, 1, 1.0, x"))
+        .unwrap_err();
+    assert_eq!(e.code(), "E_DUPLICATE_TX");
+    assert_eq!(rendered(&engine.client(&1.into()).unwrap().total), "1");
+    // the stored tx is still the first one
+    assert_eq!(engine.tx(&1.into()).unwrap().internal_txid, 0.into());
+}
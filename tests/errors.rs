@@ -0,0 +1,141 @@
+use payment_engine::{
+    client::ClTxError,
+    tx::{TxError, TxType},
+    ExternalTx,
+};
+use serde_json::json;
+
+fn tx_error(error: ClTxError) -> TxError {
+    let extx = ExternalTx {
+        ty: TxType::Dispute,
+        client: 2.into(),
+        txid: 5.into(),
+        amount: None,
+        destination: None,
+        asset: None,
+        reason: None,
+        expires_at: None,
+        timestamp: None,
+        row: None,
+    };
+    extx.client_error(error, 7.into())
+}
+
+#[test]
+fn error_codes() {
+    use ClTxError::*;
+    let amount = || rust_decimal::Decimal::new(-1, 0).into();
+    let errors = vec![
+        MissingAmountError,
+        InsufficientFoundsError(amount(), amount()),
+        ExpectingEmptyAmountError(amount()),
+        NegativeAmountError(amount()),
+        ZeroAmountError,
+        AmountOverflowError(amount(), amount()),
+        TooManyDecimalsError {
+            amount: amount(),
+            decimals: 2,
+        },
+        DifferentClientError {
+            incoming: 1.into(),
+            stored: 2.into(),
+        },
+        LockedClientError,
+        FrozenClientError,
+        ClosedClientError,
+        ActiveClientError,
+        MissingReasonError,
+        ClosingWithHeldFundsError {
+            asset: None,
+            held: amount(),
+        },
+        MissingDestinationError,
+        SelfTransferError,
+        TransferUnsupportedError,
+        HoldNotFoundError(1.into()),
+        NonHoldError(1.into()),
+        HoldSettledError(1.into()),
+        CreditLimitExceededError {
+            amount: amount(),
+            limit: amount(),
+        },
+        UnexpectedTxTypeError(TxType::Dispute),
+        DuplicateTxIdError(1.into()),
+        DisputationOnANotFoundTxIdError(1.into()),
+        DisputationOnNonDepositError(1.into()),
+        DisputationOnAlreadyDisputedTxError(1.into()),
+        ResolvingOnANotFoundTxIdError(1.into()),
+        ResolvingOnNonDepositError(1.into()),
+        ResolvingOnNonDisputedTxError(1.into()),
+        ChargebackOnANotFoundTxIdError(1.into()),
+        ChargebackOnNonDepositError(1.into()),
+        ChargebackOnNonDisputedTxError(1.into()),
+    ];
+    let mut codes = std::collections::HashSet::new();
+    for error in &errors {
+        // a new variant must also be added into the list above
+        match error {
+            MissingAmountError
+            | InsufficientFoundsError(..)
+            | ExpectingEmptyAmountError(..)
+            | NegativeAmountError(..)
+            | ZeroAmountError
+            | AmountOverflowError(..)
+            | TooManyDecimalsError { .. }
+            | DifferentClientError { .. }
+            | LockedClientError
+            | FrozenClientError
+            | ClosedClientError
+            | ActiveClientError
+            | MissingReasonError
+            | ClosingWithHeldFundsError { .. }
+            | MissingDestinationError
+            | SelfTransferError
+            | TransferUnsupportedError
+            | HoldNotFoundError(..)
+            | NonHoldError(..)
+            | HoldSettledError(..)
+            | CreditLimitExceededError { .. }
+            | UnexpectedTxTypeError(..)
+            | DuplicateTxIdError(..)
+            | DisputationOnANotFoundTxIdError(..)
+            | DisputationOnNonDepositError(..)
+            | DisputationOnAlreadyDisputedTxError(..)
+            | ResolvingOnANotFoundTxIdError(..)
+            | ResolvingOnNonDepositError(..)
+            | ResolvingOnNonDisputedTxError(..)
+            | ChargebackOnANotFoundTxIdError(..)
+            | ChargebackOnNonDepositError(..)
+            | ChargebackOnNonDisputedTxError(..) => (),
+        }
+        let value = serde_json::to_value(error).unwrap();
+        assert_eq!(value["code"], error.code());
+        assert!(codes.insert(error.code()), "repeated {}", error.code());
+    }
+    assert_eq!(codes.len(), 32);
+}
+
+#[test]
+fn tx_error_serialization() {
+    let error = tx_error(ClTxError::DisputationOnANotFoundTxIdError(5.into()));
+    assert_eq!(
+        serde_json::to_value(&error).unwrap(),
+        json!({
+            "txid": 5,
+            "internal_txid": 7,
+            "error": {
+                "code": "E_DISPUTE_TX_NOT_FOUND",
+                "details": 5,
+            },
+        })
+    );
+}
+
+#[test]
+fn tx_error_accessors() {
+    let error = tx_error(ClTxError::LockedClientError);
+    assert_eq!(error.txid(), &5.into());
+    assert_eq!(error.internal_txid(), &7.into());
+    assert_eq!(error.code(), "E_LOCKED_CLIENT");
+    assert_eq!(error.into_error(), ClTxError::LockedClientError);
+}
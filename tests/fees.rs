@@ -0,0 +1,134 @@
+use payment_engine::{AccountStatus, Engine, Policy};
+
+mod common;
+use common::{b, balances, tx};
+
+fn fees() -> Policy {
+    Policy::from_toml(
+        r#"
+        [fees]
+        house = 0
+        withdrawal = { flat = "0.5" }
+        chargeback = { tiered = [
+            { from = "0", fee = { flat = "2" } },
+            { from = "100", fee = { percentage = "10" } },
+        ] }
+
+        [[fees.clients]]
+        client = 2
+        withdrawal = { percentage = "1" }
+        "#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn fees_withdrawal() {
+    let mut engine = Engine::new(fees());
+    assert_eq!(balances(&engine, 0), b("0", "0", "0"));
+
+    engine.process(&tx("deposit, 1, 1, 10")).unwrap();
+    engine.process(&tx("withdrawal, 1, 2, 4")).unwrap();
+    assert_eq!(balances(&engine, 1), b("5.5", "0", "5.5"));
+    assert_eq!(balances(&engine, 0), b("0.5", "0", "0.5"));
+
+    // per client
+    engine.process(&tx("deposit, 2, 3, 300")).unwrap();
+    engine.process(&tx("withdrawal, 2, 4, 200")).unwrap();
+    assert_eq!(balances(&engine, 2), b("98", "0", "98"));
+    assert_eq!(balances(&engine, 0), b("2.5", "0", "2.5"));
+
+    // the house isn't charged
+    engine.process(&tx("withdrawal, 0, 5, 1")).unwrap();
+    assert_eq!(balances(&engine, 0), b("1.5", "0", "1.5"));
+}
+
+#[test]
+fn fees_not_charged_on_failure() {
+    let mut engine = Engine::new(fees());
+    engine.process(&tx("deposit, 1, 1, 10")).unwrap();
+
+    let e = engine.process(&tx("withdrawal, 1, 2, 11")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+    // the tx could be applied, but not its fee
+    let e = engine.process(&tx("withdrawal, 1, 3, 10")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+    let e = engine.process(&tx("chargeback, 1, 1,")).unwrap_err();
+    assert_eq!(e.code(), "E_CHARGEBACK_NOT_DISPUTED");
+
+    assert_eq!(balances(&engine, 1), b("10", "0", "10"));
+    assert_eq!(balances(&engine, 0), b("0", "0", "0"));
+}
+
+#[test]
+fn fees_duplicate_not_applied() {
+    let mut engine = Engine::new(fees());
+    engine.process(&tx("deposit, 1, 1, 10")).unwrap();
+    engine.process(&tx("withdrawal, 1, 2, 4")).unwrap();
+
+    // neither the tx nor its fee is applied again
+    let e = engine.process(&tx("withdrawal, 1, 2, 4")).unwrap_err();
+    assert_eq!(e.code(), "E_DUPLICATE_TX");
+    let e = engine.process(&tx("deposit, 2, 2, 4")).unwrap_err();
+    assert_eq!(e.code(), "E_DUPLICATE_TX");
+    assert_eq!(balances(&engine, 1), b("5.5", "0", "5.5"));
+    assert_eq!(balances(&engine, 0), b("0.5", "0", "0.5"));
+    assert_eq!(balances(&engine, 2), b("0", "0", "0"));
+}
+
+#[test]
+fn fees_chargeback_tiers() {
+    let mut engine = Engine::new(fees());
+    engine.process(&tx("deposit, 1, 1, 300")).unwrap();
+    engine.process(&tx("deposit, 1, 2, 200")).unwrap();
+    engine.process(&tx("deposit, 1, 3, 50")).unwrap();
+
+    engine.process(&tx("dispute, 1, 2,")).unwrap();
+    engine.process(&tx("chargeback, 1, 2,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("330", "0", "330"));
+
+    engine.process(&tx("dispute, 1, 3,")).unwrap();
+    engine.process(&tx("chargeback, 1, 3,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("278", "0", "278"));
+    assert_eq!(balances(&engine, 0), b("22", "0", "22"));
+}
+
+#[test]
+fn fees_chargeback_capped() {
+    let policy = Policy::from_toml(
+        r#"
+        [fees]
+        house = 0
+        chargeback = { flat = "15" }
+        "#,
+    )
+    .unwrap();
+    let mut engine = Engine::new(policy.clone());
+    engine.process(&tx("deposit, 1, 1, 10")).unwrap();
+    engine.process(&tx("dispute, 1, 1,")).unwrap();
+    // the chargeback can't be refused, and nothing is left for its fee
+    engine.process(&tx("chargeback, 1, 1,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("0", "0", "0"));
+    assert!(engine.client(&1.into()).unwrap().status != AccountStatus::Active);
+    assert_eq!(balances(&engine, 0), b("0", "0", "0"));
+
+    let mut engine = Engine::new(policy.clone());
+    engine.process(&tx("deposit, 1, 1, 10")).unwrap();
+    engine.process(&tx("deposit, 1, 2, 6")).unwrap();
+    engine.process(&tx("dispute, 1, 1,")).unwrap();
+    engine.process(&tx("chargeback, 1, 1,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("0", "0", "0"));
+    assert_eq!(balances(&engine, 0), b("6", "0", "6"));
+
+    // or the fee leaves a debt
+    let policy = Policy {
+        signed_balances: true,
+        ..policy
+    };
+    let mut engine = Engine::new(policy);
+    engine.process(&tx("deposit, 1, 1, 10")).unwrap();
+    engine.process(&tx("dispute, 1, 1,")).unwrap();
+    engine.process(&tx("chargeback, 1, 1,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("-15", "0", "-15"));
+    assert_eq!(balances(&engine, 0), b("15", "0", "15"));
+}
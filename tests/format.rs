@@ -0,0 +1,66 @@
+use payment_engine::{Format, Policy};
+
+const CSV: &str = "type, client, tx, amount
+deposit, 1, 1, 2.0
+withdrawal, 1, 2, 0.5
+deposit, 2, 3, 1.0
+";
+
+const JSONL: &str = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.0"}
+{"type": "withdrawal", "client": 1, "tx": 2, "amount": 0.5}
+
+{"type": "deposit", "client": 2, "tx": 3, "amount": "1.0"}
+"#;
+
+fn run(format: Format, input: &str) -> Vec<payment_engine::Client> {
+    let rows = format.read_input(input.as_bytes());
+    let clients = payment_engine::run_rows(rows, &Policy::default(), |_| Ok(())).unwrap();
+    let mut clients: Vec<_> = clients.into_values().collect();
+    clients.sort_by(|a, b| a.id.cmp(&b.id));
+    clients
+}
+
+#[test]
+fn format_jsonl_same_as_csv() {
+    assert_eq!(run(Format::Csv, CSV), run(Format::JsonLines, JSONL));
+}
+
+#[test]
+fn format_jsonl_malformed() {
+    let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.0"}
+{"type": "deposit", "client": 1
+{"type": "deposit", "client": 1, "tx": 2, "amount": "1.0"}
+"#;
+    let mut rejects = vec![];
+    let rows = Format::JsonLines.read_input(input.as_bytes());
+    payment_engine::run_rows(rows, &Policy::default(), |reject| {
+        rejects.push(reject);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(rejects.len(), 1);
+    assert_eq!(rejects[0].line, 2);
+    assert_eq!(rejects[0].code, "E_MALFORMED_ROW");
+}
+
+#[test]
+fn format_jsonl_output() {
+    let clients = run(Format::Csv, CSV);
+    let mut output = vec![];
+    Format::JsonLines
+        .write_output(clients.into_iter(), &mut output)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        r#"{"client":1,"available":"1.5","held":"0","total":"1.5","locked":false}
+{"client":2,"available":"1","held":"0","total":"1","locked":false}
+"#
+    );
+}
+
+#[test]
+fn format_parse() {
+    assert_eq!("csv".parse(), Ok(Format::Csv));
+    assert_eq!("jsonl".parse(), Ok(Format::JsonLines));
+    assert!("xml".parse::<Format>().is_err());
+}
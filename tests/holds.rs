@@ -0,0 +1,119 @@
+use payment_engine::{Engine, ExternalTx, Policy};
+
+mod common;
+use common::{b, balances, engine_with, hold_tx as tx};
+
+/// The txs of a client 1 that authorized 4 out of 10.
+fn authorized() -> [ExternalTx; 2] {
+    [tx("deposit, 1, 1, 10,,"), tx("authorize, 1, 2, 4,,")]
+}
+
+#[test]
+fn hold_capture_and_void() {
+    let mut engine = engine_with(Policy::default(), &authorized());
+    assert_eq!(balances(&engine, 1), b("6", "4", "10"));
+    engine.process(&tx("capture, 1, 2,,,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("6", "0", "6"));
+    let e = engine.process(&tx("void, 1, 2,,,")).unwrap_err();
+    assert_eq!(e.code(), "E_HOLD_SETTLED");
+
+    engine.process(&tx("authorize, 1, 3, 5,,")).unwrap();
+    engine.process(&tx("void, 1, 3,,,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("6", "0", "6"));
+}
+
+#[test]
+fn hold_errors() {
+    let mut engine = engine_with(Policy::default(), &authorized());
+    let e = engine.process(&tx("authorize, 1, 3, 7,,")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+    let e = engine.process(&tx("capture, 1, 9,,,")).unwrap_err();
+    assert_eq!(e.code(), "E_HOLD_NOT_FOUND");
+    let e = engine.process(&tx("capture, 1, 1,,,")).unwrap_err();
+    assert_eq!(e.code(), "E_NOT_A_HOLD");
+    let e = engine.process(&tx("capture, 2, 2,,,")).unwrap_err();
+    assert_eq!(e.code(), "E_DIFFERENT_CLIENT");
+    let e = engine.process(&tx("dispute, 1, 2,,,")).unwrap_err();
+    assert_eq!(e.code(), "E_DISPUTE_NON_DEPOSIT");
+    assert_eq!(balances(&engine, 1), b("6", "4", "10"));
+}
+
+#[test]
+fn hold_expiry_by_count() {
+    let policy = Policy {
+        hold_expiry: Some(2),
+        ..Policy::default()
+    };
+    let mut engine = engine_with(policy, &authorized());
+    engine.process(&tx("deposit, 2, 3, 1,,")).unwrap();
+    engine.process(&tx("deposit, 2, 4, 1,,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("6", "4", "10"));
+
+    // voided before this third subsequent tx is processed
+    let e = engine.process(&tx("capture, 1, 2,,,")).unwrap_err();
+    assert_eq!(e.code(), "E_HOLD_SETTLED");
+    assert_eq!(balances(&engine, 1), b("10", "0", "10"));
+}
+
+#[test]
+fn hold_expiry_by_timestamp() {
+    let mut engine = Engine::default();
+    engine.process(&tx("deposit, 1, 1, 10,,")).unwrap();
+    engine.process(&tx("authorize, 1, 2, 4, 1000,")).unwrap();
+    engine.process(&tx("authorize, 1, 3, 5, 2000,")).unwrap();
+    engine.process(&tx("deposit, 2, 4, 1,, 999")).unwrap();
+    assert_eq!(balances(&engine, 1), b("1", "9", "10"));
+    engine.process(&tx("deposit, 2, 5, 1,, 1500")).unwrap();
+    assert_eq!(balances(&engine, 1), b("5", "5", "10"));
+
+    // the pending holds are kept in the snapshots
+    let mut engine = Engine::from_snapshot(engine.snapshot(), Policy::default()).unwrap();
+    engine.process(&tx("deposit, 2, 6, 1,, 2000")).unwrap();
+    assert_eq!(balances(&engine, 1), b("10", "0", "10"));
+}
+
+#[test]
+fn hold_expiry_parallel() {
+    let policy = Policy {
+        hold_expiry: Some(1),
+        ..Policy::default()
+    };
+    let input = "type, client, tx, amount, expires_at, timestamp\n\
+                 deposit, 1, 1, 10,,\n\
+                 authorize, 1, 2, 4,,\n\
+                 deposit, 3, 3, 10,,\n\
+                 authorize, 3, 4, 4, 1000,\n\
+                 deposit, 2, 5, 1,, 1000\n\
+                 deposit, 2, 6, 1,,\n";
+    let rows = || payment_engine::read_input(input.as_bytes());
+    let serial = payment_engine::run_rows(rows(), &policy, |_reject| Ok(())).unwrap();
+    for workers in &[1, 2, 4] {
+        let parallel =
+            payment_engine::parallel::run_rows(rows(), *workers, &policy, |_reject| Ok(()))
+                .unwrap();
+        assert_eq!(serial, parallel);
+    }
+    let client = &serial[&1.into()];
+    assert_eq!(client.held.to_string(), "0");
+    assert_eq!(serial[&3.into()].held.to_string(), "0");
+}
+
+#[test]
+fn hold_expiry_parallel_untimed() {
+    // a later tx without a timestamp doesn't expire the hold
+    let input = "type, client, tx, amount, expires_at, timestamp\n\
+                 deposit, 1, 1, 10,,\n\
+                 deposit, 2, 2, 1,, 1000\n\
+                 authorize, 1, 3, 4, 500,\n\
+                 capture, 1, 3,,,\n";
+    let rows = || payment_engine::read_input(input.as_bytes());
+    let policy = Policy::default();
+    let serial = payment_engine::run_rows(rows(), &policy, |_reject| Ok(())).unwrap();
+    assert_eq!(serial[&1.into()].total.to_string(), "6");
+    for workers in &[1, 2, 4] {
+        let parallel =
+            payment_engine::parallel::run_rows(rows(), *workers, &policy, |_reject| Ok(()))
+                .unwrap();
+        assert_eq!(serial, parallel);
+    }
+}
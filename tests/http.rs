@@ -0,0 +1,109 @@
+use payment_engine::{http::HttpServer, Engine};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+fn start() -> SocketAddr {
+    let engine = Arc::new(Mutex::new(Engine::default()));
+    let server = HttpServer::bind("127.0.0.1:0", engine).unwrap();
+    let addr = server.local_addr();
+    thread::spawn(move || server.run());
+    addr
+}
+
+/// Sends a request, returning the status code and the body.
+fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
+
+fn json(body: &str) -> serde_json::Value {
+    serde_json::from_str(body).unwrap()
+}
+
+#[test]
+fn http_post_and_query() {
+    let addr = start();
+
+    let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "3.5"}"#;
+    let (status, body) = request(addr, "POST", "/transactions", deposit);
+    assert_eq!(status, 200);
+    assert_eq!(json(&body)["status"], "accepted");
+    assert_eq!(json(&body)["internal_tx"], 0);
+
+    let withdrawal = r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "5"}"#;
+    let (status, body) = request(addr, "POST", "/transactions", withdrawal);
+    assert_eq!(status, 422);
+    assert_eq!(json(&body)["status"], "rejected");
+    assert_eq!(json(&body)["code"], "E_INSUFFICIENT_FUNDS");
+
+    let dispute = r#"{"type": "dispute", "client": 1, "tx": 1, "amount": null}"#;
+    let (status, _) = request(addr, "POST", "/transactions", dispute);
+    assert_eq!(status, 200);
+
+    let (status, body) = request(addr, "GET", "/clients/1", "");
+    assert_eq!(status, 200);
+    let client = &json(&body)[0];
+    assert_eq!(client["available"], "0.0");
+    assert_eq!(client["held"], "3.5");
+    assert_eq!(client["total"], "3.5");
+    assert_eq!(client["locked"], false);
+
+    let (status, body) = request(addr, "GET", "/transactions/1", "");
+    assert_eq!(status, 200);
+    assert_eq!(json(&body)["disputed"], true);
+
+    let (status, body) = request(addr, "GET", "/clients", "");
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        "client,available,held,total,locked\r\n1,0.0,3.5,3.5,false\r\n"
+    );
+
+    // one row per asset
+    let deposit = r#"{"type": "deposit", "client": 2, "tx": 3, "amount": "2", "asset": "EUR"}"#;
+    let (status, _) = request(addr, "POST", "/transactions", deposit);
+    assert_eq!(status, 200);
+    let (status, body) = request(addr, "GET", "/clients/2", "");
+    assert_eq!(status, 200);
+    let rows = json(&body);
+    assert_eq!(rows.as_array().unwrap().len(), 1);
+    assert_eq!(rows[0]["asset"], "EUR");
+    assert_eq!(rows[0]["total"], "2");
+}
+
+#[test]
+fn http_errors() {
+    let addr = start();
+
+    let (status, body) = request(addr, "GET", "/clients/1", "");
+    assert_eq!(status, 404);
+    assert_eq!(json(&body)["code"], "E_NOT_FOUND");
+
+    let (status, body) = request(addr, "GET", "/transactions/x", "");
+    assert_eq!(status, 400);
+    assert_eq!(json(&body)["code"], "E_BAD_REQUEST");
+
+    let (status, body) = request(addr, "POST", "/transactions", "{");
+    assert_eq!(status, 400);
+    assert_eq!(json(&body)["code"], "E_BAD_REQUEST");
+
+    let (status, _) = request(addr, "DELETE", "/clients", "");
+    assert_eq!(status, 404);
+}
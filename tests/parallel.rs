@@ -0,0 +1,53 @@
+use payment_engine::ExternalTx;
+use std::path::PathBuf;
+
+mod common;
+
+/// The txs of `common::generate_csv`.
+fn generate(len: u32, clients: u16) -> Vec<ExternalTx> {
+    let csv = common::generate_csv(len, clients);
+    payment_engine::read_input(csv.as_bytes())
+        .map(|row| row.unwrap().tx.unwrap())
+        .collect()
+}
+
+#[test]
+fn parallel_same_as_serial() {
+    let txs = generate(20_000, 100);
+    let serial = payment_engine::run(txs.clone().into_iter());
+    for workers in &[1, 2, 7] {
+        let parallel = payment_engine::parallel::run(txs.clone().into_iter(), *workers);
+        assert_eq!(serial, parallel);
+    }
+}
+
+#[test]
+fn parallel_rows_rejects() {
+    let read = || payment_engine::read_input_file(&PathBuf::from("tests/basic_rejects.csv"));
+    let mut serial_rejects = vec![];
+    let serial = payment_engine::run_rows(read().unwrap(), &Default::default(), |reject| {
+        serial_rejects.push(reject);
+        Ok(())
+    })
+    .unwrap();
+
+    let mut parallel_rejects = vec![];
+    let parallel =
+        payment_engine::parallel::run_rows(read().unwrap(), 3, &Default::default(), |reject| {
+            parallel_rejects.push(reject);
+            Ok(())
+        })
+        .unwrap();
+
+    parallel_rejects.sort_by_key(|reject| reject.line);
+    assert_eq!(serial, parallel);
+    assert_eq!(serial_rejects, parallel_rejects);
+}
+
+#[test]
+fn parallel_rejects_fees() {
+    let policy = payment_engine::Policy::from_toml("[fees]\nhouse = 0\n").unwrap();
+    let rows = payment_engine::read_input_file(&PathBuf::from("tests/basic_rejects.csv")).unwrap();
+    let result = payment_engine::parallel::run_rows(rows, 3, &policy, |_reject| Ok(()));
+    assert!(result.is_err());
+}
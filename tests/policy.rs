@@ -0,0 +1,88 @@
+use payment_engine::{policy::Precision, Engine, Policy};
+
+mod common;
+use common::tx;
+
+#[test]
+fn policy_files() {
+    let expected = Policy {
+        idempotent_replays: true,
+        withdrawal_disputes: true,
+        locked_deposits: true,
+        precision: Some(Precision {
+            decimals: 4,
+            round: true,
+        }),
+        ..Default::default()
+    };
+    assert_eq!(Policy::load("tests/policy.toml").unwrap(), expected);
+
+    let json = r#"{"withdrawal_disputes": true, "precision": {"decimals": 2}}"#;
+    let policy = Policy::from_json(json).unwrap();
+    assert!(policy.withdrawal_disputes);
+    assert!(!policy.idempotent_replays);
+    assert_eq!(policy.precision.unwrap().decimals, 2);
+    assert!(!policy.precision.unwrap().round);
+
+    // every field is optional
+    assert_eq!(Policy::from_toml("").unwrap(), Policy::default());
+}
+
+#[test]
+fn policy_invalid() {
+    // a typo isn't silently ignored
+    assert!(Policy::from_toml("idempotent_replay = true").is_err());
+    assert!(Policy::from_toml("idempotent_replays = 1").is_err());
+    assert!(Policy::from_json(r#"{"precision": {"decimals": 29}}"#).is_err());
+    let e = Policy::load("tests/missing.toml").unwrap_err();
+    assert!(e.to_string().contains("No such file"));
+}
+
+#[test]
+fn policy_locked_deposits() {
+    let rows = ["deposit, 1, 1, 10", "dispute, 1, 1,", "chargeback, 1, 1,"];
+
+    let mut engine = Engine::default();
+    for row in &rows {
+        engine.process(&tx(row)).unwrap();
+    }
+    let e = engine.process(&tx("deposit, 1, 2, 5")).unwrap_err();
+    assert_eq!(e.code(), "E_LOCKED_CLIENT");
+
+    let mut engine = Engine::new(Policy::from_toml("locked_deposits = true").unwrap());
+    for row in &rows {
+        engine.process(&tx(row)).unwrap();
+    }
+    engine.process(&tx("deposit, 1, 2, 5")).unwrap();
+    // but not withdrawals
+    let e = engine.process(&tx("withdrawal, 1, 3, 5")).unwrap_err();
+    assert_eq!(e.code(), "E_LOCKED_CLIENT");
+    assert_eq!(engine.client(&1.into()).unwrap().total.to_string(), "5");
+}
+
+#[test]
+fn policy_statuses() {
+    let rows = ["deposit, 1, 1, 10", "dispute, 1, 1,", "chargeback, 1, 1,"];
+    let policy = Policy::from_toml(
+        r#"
+        [statuses]
+        locked = ["deposit", "withdrawal"]
+        active = ["deposit", "dispute", "chargeback"]
+        "#,
+    )
+    .unwrap();
+    let mut engine = Engine::new(policy);
+    let e = engine.process(&tx("withdrawal, 1, 9, 1")).unwrap_err();
+    assert_eq!(e.code(), "E_ACTIVE_CLIENT");
+    for row in &rows {
+        engine.process(&tx(row)).unwrap();
+    }
+    engine.process(&tx("deposit, 1, 2, 5")).unwrap();
+    engine.process(&tx("withdrawal, 1, 3, 2")).unwrap();
+    // the disputes of a locked client are no longer accepted
+    let e = engine.process(&tx("dispute, 1, 2,")).unwrap_err();
+    assert_eq!(e.code(), "E_LOCKED_CLIENT");
+    assert_eq!(engine.client(&1.into()).unwrap().total.to_string(), "3");
+
+    assert!(Policy::from_toml("[statuses]\nlocked = [\"deposits\"]").is_err());
+}
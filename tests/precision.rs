@@ -0,0 +1,63 @@
+use payment_engine::{policy::Precision, Client, Policy, Reject};
+
+const INPUT: &str = "type, client, tx, amount
+deposit, 1, 1, 1.5
+deposit, 1, 2, 0.12345
+withdrawal, 1, 3, 0.00005
+dispute, 1, 2,
+";
+
+fn run(round: bool) -> (Vec<Client>, Vec<Reject>, Policy) {
+    let policy = Policy {
+        precision: Some(Precision { decimals: 4, round }),
+        ..Policy::default()
+    };
+    let mut rejects = vec![];
+    let rows = payment_engine::read_input(INPUT.as_bytes());
+    let clients = payment_engine::run_rows(rows, &policy, |reject| {
+        rejects.push(reject);
+        Ok(())
+    })
+    .unwrap();
+    let clients = clients.into_values().collect();
+    (clients, rejects, policy)
+}
+
+fn output(clients: Vec<Client>, policy: &Policy) -> String {
+    let mut output = vec![];
+    let clients = clients.into_iter().map(|client| policy.render(client));
+    payment_engine::write_output(clients, &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn precision_reject() {
+    let (clients, rejects, policy) = run(false);
+    let codes: Vec<_> = rejects.iter().map(|reject| reject.code).collect();
+    // the dispute refers to a rejected tx
+    assert_eq!(
+        codes,
+        vec![
+            "E_TOO_MANY_DECIMALS",
+            "E_TOO_MANY_DECIMALS",
+            "E_DISPUTE_TX_NOT_FOUND"
+        ]
+    );
+    assert_eq!(
+        output(clients, &policy),
+        "client,available,held,total,locked\r\n1,1.5000,0.0000,1.5000,false\r\n"
+    );
+}
+
+#[test]
+fn precision_round() {
+    let (clients, rejects, policy) = run(true);
+    let codes: Vec<_> = rejects.iter().map(|reject| reject.code).collect();
+    // 0.12345 is rounded into 0.1234 (half to even), which is the amount
+    // held by the dispute, and 0.00005 into a rejected zero
+    assert_eq!(codes, vec!["E_ZERO_AMOUNT"]);
+    assert_eq!(
+        output(clients, &policy),
+        "client,available,held,total,locked\r\n1,1.5000,0.1234,1.6234,false\r\n"
+    );
+}
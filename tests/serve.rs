@@ -0,0 +1,109 @@
+use payment_engine::{
+    serve::{self, Ack, Server, Status},
+    Engine,
+};
+use std::{
+    io::Write,
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+fn start() -> (SocketAddr, Arc<Mutex<Engine>>) {
+    let server = Server::bind("127.0.0.1:0", Engine::default()).unwrap();
+    let addr = server.local_addr().unwrap();
+    let engine = server.engine();
+    thread::spawn(move || server.run());
+    (addr, engine)
+}
+
+/// Sends all of the input, then reads all of the acks.
+fn send(addr: SocketAddr, input: &str) -> Vec<Ack> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(input.as_bytes()).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    serve::read_acks(stream).map(Result::unwrap).collect()
+}
+
+#[test]
+fn serve_acks() {
+    let (addr, engine) = start();
+    let acks = send(
+        addr,
+        "type, client, tx, amount
+deposit, 1, 1, 3.0
+withdrawal, 1, 2, 5.0
+deposit, 1,
+dispute, 1, 1,
+",
+    );
+    let statuses: Vec<_> = acks.iter().map(|ack| ack.status).collect();
+    assert_eq!(
+        statuses,
+        vec![
+            Status::Accepted,
+            Status::Rejected,
+            Status::Rejected,
+            Status::Accepted
+        ]
+    );
+    let codes: Vec<_> = acks.iter().map(|ack| ack.code.as_str()).collect();
+    assert_eq!(
+        codes,
+        vec!["", "E_INSUFFICIENT_FUNDS", "E_MALFORMED_ROW", ""]
+    );
+    assert_eq!(acks[1].line, 3);
+    assert_eq!(acks[3].internal_txid, 3.into());
+
+    let engine = engine.lock().unwrap();
+    assert!(engine.tx(&1.into()).unwrap().is_disputed());
+}
+
+#[test]
+fn serve_many_connections() {
+    const CONNECTIONS: u32 = 1000;
+    // limits the open file descriptors
+    const CONCURRENT: u32 = 250;
+    let (addr, engine) = start();
+
+    let handles: Vec<_> = (0..CONCURRENT)
+        .map(|thread| {
+            thread::spawn(move || {
+                for i in (thread..CONNECTIONS).step_by(CONCURRENT as usize) {
+                    // each connection deposits twice into one of 10
+                    // clients, and then withdraws once
+                    let input = format!(
+                        "type, client, tx, amount
+deposit, {client}, {}, 2.0
+deposit, {client}, {}, 1.0
+withdrawal, {client}, {}, 1.0
+",
+                        i * 3 + 1,
+                        i * 3 + 2,
+                        i * 3 + 3,
+                        client = i % 10
+                    );
+                    let acks = send(addr, &input);
+                    assert_eq!(acks.len(), 3);
+                    assert!(acks.iter().all(|ack| ack.status == Status::Accepted));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let engine = engine.lock().unwrap();
+    assert_eq!(engine.next_internal_txid(), &(CONNECTIONS * 3).into());
+    let mut total = 0.0;
+    for client in engine.clients() {
+        total += serde_json::to_value(&client.total)
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .parse::<f64>()
+            .unwrap();
+    }
+    assert_eq!(total, (CONNECTIONS * 2) as f64);
+}
@@ -0,0 +1,60 @@
+use payment_engine::{Engine, Policy};
+
+mod common;
+use common::{b, balances, engine_with, tx};
+
+fn signed() -> Policy {
+    Policy {
+        signed_balances: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn signed_unsigned_rejects() {
+    let mut engine = Engine::default();
+    engine.process(&tx("deposit, 1, 1, 10")).unwrap();
+    engine.process(&tx("withdrawal, 1, 2, 8")).unwrap();
+    let e = engine.process(&tx("dispute, 1, 1,")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+    assert_eq!(balances(&engine, 1), b("2", "0", "2"));
+}
+
+#[test]
+fn signed_chargeback_debt() {
+    let mut engine = engine_with(
+        signed(),
+        &[tx("deposit, 1, 1, 10"), tx("withdrawal, 1, 2, 8")],
+    );
+    engine.process(&tx("dispute, 1, 1,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("-8", "10", "2"));
+    // the negative available can't be withdrawn from
+    let e = engine.process(&tx("withdrawal, 1, 3, 1")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+
+    engine.process(&tx("chargeback, 1, 1,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("-8", "0", "-8"));
+    let client = engine.client(&1.into()).unwrap();
+    assert_eq!(client.debt().unwrap().to_string(), "8");
+
+    let mut output = vec![];
+    payment_engine::write_output(engine.finish().into_values(), &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked,debt\r\n1,-8,0,-8,true,8\r\n"
+    );
+}
+
+#[test]
+fn signed_resolve() {
+    let mut engine = engine_with(
+        signed(),
+        &[tx("deposit, 1, 1, 10"), tx("withdrawal, 1, 2, 8")],
+    );
+    engine.process(&tx("dispute, 1, 1,")).unwrap();
+    engine.process(&tx("deposit, 1, 3, 5")).unwrap();
+    assert_eq!(balances(&engine, 1), b("-3", "10", "7"));
+    engine.process(&tx("resolve, 1, 1,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("7", "0", "7"));
+    assert!(engine.client(&1.into()).unwrap().debt().is_none());
+}
@@ -0,0 +1,61 @@
+use payment_engine::{Clients, Policy, Snapshot};
+
+const DAY1: &str = "type, client, tx, amount
+deposit, 1, 1, 10.0
+deposit, 2, 2, 5.0
+dispute, 2, 2,
+withdrawal, 1, 3, 2.5
+";
+
+const DAY2: &str = "type, client, tx, amount
+resolve, 2, 2,
+deposit, 1, 4, 1.0
+dispute, 1, 1,
+";
+
+fn run_from(snapshot: Snapshot, input: &str) -> Snapshot {
+    let rows = payment_engine::read_input(input.as_bytes());
+    payment_engine::run_rows_from_snapshot(snapshot, rows, &Policy::default(), |_| Ok(())).unwrap()
+}
+
+fn clients(snapshot: &Snapshot) -> Clients {
+    snapshot
+        .clients
+        .iter()
+        .map(|client| (client.id.clone(), client.clone()))
+        .collect()
+}
+
+#[test]
+fn snapshot_between_days() {
+    let day1 = run_from(Snapshot::default(), DAY1);
+    assert_eq!(day1.next_internal_txid, 4.into());
+    assert!(day1.txs.iter().any(|tx| tx.is_disputed()));
+
+    let mut exported = vec![];
+    day1.write(&mut exported).unwrap();
+    let imported = Snapshot::read(exported.as_slice()).unwrap();
+    assert_eq!(day1, imported);
+    let day2 = run_from(imported, DAY2);
+
+    let both = format!(
+        "{}{}",
+        DAY1,
+        DAY2.lines().skip(1).collect::<Vec<_>>().join("\n")
+    );
+    let rows = payment_engine::read_input(both.as_bytes());
+    let expected = payment_engine::run_rows(rows, &Policy::default(), |_| Ok(())).unwrap();
+    assert_eq!(clients(&day2), expected);
+    assert_eq!(day2.next_internal_txid, 7.into());
+}
+
+#[test]
+fn snapshot_version() {
+    let snapshot = Snapshot {
+        version: Snapshot::VERSION + 1,
+        ..Snapshot::default()
+    };
+    let mut exported = vec![];
+    snapshot.write(&mut exported).unwrap();
+    assert!(Snapshot::read(exported.as_slice()).is_err());
+}
@@ -0,0 +1,81 @@
+use payment_engine::{AccountStatus, ClientRow, Engine, Policy};
+
+mod common;
+use common::reason_tx as tx;
+
+fn status(engine: &Engine) -> AccountStatus {
+    engine.client(&1.into()).unwrap().status
+}
+
+fn code(engine: &mut Engine, row: &str) -> &'static str {
+    engine.process(&tx(row)).unwrap_err().code()
+}
+
+#[test]
+fn status_locked() {
+    let mut engine = Engine::default();
+    for row in &[
+        "deposit, 1, 1, 10,",
+        "deposit, 1, 2, 5,",
+        "dispute, 1, 1,,",
+        "chargeback, 1, 1,,",
+    ] {
+        engine.process(&tx(row)).unwrap();
+    }
+    assert_eq!(status(&engine), AccountStatus::Locked);
+
+    assert_eq!(code(&mut engine, "deposit, 1, 3, 1,"), "E_LOCKED_CLIENT");
+    assert_eq!(code(&mut engine, "withdrawal, 1, 4, 1,"), "E_LOCKED_CLIENT");
+    assert_eq!(code(&mut engine, "freeze, 1, 5,, fraud"), "E_LOCKED_CLIENT");
+    // disputes are still accepted
+    engine.process(&tx("dispute, 1, 2,,")).unwrap();
+    engine.process(&tx("resolve, 1, 2,,")).unwrap();
+
+    assert_eq!(code(&mut engine, "unlock, 1, 6,,"), "E_MISSING_REASON");
+    let unlock = tx("unlock, 1, 6,, chargeback reviewed");
+    assert_eq!(unlock.reason.as_deref(), Some("chargeback reviewed"));
+    engine.process(&unlock).unwrap();
+    assert_eq!(status(&engine), AccountStatus::Active);
+    assert_eq!(code(&mut engine, "unlock, 1, 7,, again"), "E_ACTIVE_CLIENT");
+    engine.process(&tx("deposit, 1, 3, 1,")).unwrap();
+
+    let row = ClientRow::from(engine.client(&1.into()).unwrap().clone());
+    assert!(!row.locked);
+    assert_eq!(row.total.to_string(), "6");
+}
+
+#[test]
+fn status_frozen_and_closed() {
+    let mut engine = Engine::new(Policy::default());
+    engine.process(&tx("deposit, 1, 1, 10,")).unwrap();
+    engine.process(&tx("freeze, 1, 2,, investigation")).unwrap();
+    assert_eq!(status(&engine), AccountStatus::Frozen);
+
+    assert_eq!(code(&mut engine, "withdrawal, 1, 3, 1,"), "E_FROZEN_CLIENT");
+    assert_eq!(code(&mut engine, "freeze, 1, 4,, again"), "E_FROZEN_CLIENT");
+    assert_eq!(
+        code(&mut engine, "close, 1, 5, 1, done"),
+        "E_UNEXPECTED_AMOUNT"
+    );
+    engine.process(&tx("dispute, 1, 1,,")).unwrap();
+    assert_eq!(
+        code(&mut engine, "close, 1, 5,, done"),
+        "E_CLOSE_HELD_FUNDS"
+    );
+    engine.process(&tx("resolve, 1, 1,,")).unwrap();
+
+    engine.process(&tx("close, 1, 5,, done")).unwrap();
+    assert_eq!(status(&engine), AccountStatus::Closed);
+    for row in &[
+        "deposit, 1, 6, 1,",
+        "dispute, 1, 1,,",
+        "unlock, 1, 7,, reopen",
+        "close, 1, 8,, again",
+    ] {
+        assert_eq!(code(&mut engine, row), "E_CLOSED_CLIENT");
+    }
+
+    let row = ClientRow::from(engine.client(&1.into()).unwrap().clone());
+    assert!(row.locked);
+    assert_eq!(row.available.to_string(), "10");
+}
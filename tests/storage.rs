@@ -0,0 +1,120 @@
+use payment_engine::{
+    storage::{DiskStorage, MemoryStorage},
+    Clients, Policy, Storage,
+};
+use std::io::Write;
+
+const INPUT: &str = "type, client, tx, amount
+deposit, 1, 1, 10.0
+deposit, 2, 2, 5.0
+withdrawal, 1, 3, 2.5
+dispute, 2, 2,
+deposit, 1, 4, 1.0
+withdrawal, 2, 5, 1.0
+resolve, 2, 2,
+dispute, 1, 4,
+malformed
+chargeback, 1, 4,
+deposit, 3, 6, 7.0
+withdrawal, 3, 7, 1.0
+";
+
+fn run(rows: usize, storage: &mut impl Storage) -> Clients {
+    let inputs = payment_engine::read_input(INPUT.as_bytes()).take(rows);
+    payment_engine::run_rows_with_storage(inputs, &Policy::default(), storage, |_| Ok(())).unwrap()
+}
+
+fn expected() -> Clients {
+    let inputs = payment_engine::read_input(INPUT.as_bytes());
+    payment_engine::run_rows(inputs, &Policy::default(), |_| Ok(())).unwrap()
+}
+
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("payment-engine-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn memory_storage_resume() {
+    for snapshot_every in 0..4 {
+        for interrupted_at in 0..12 {
+            let mut storage = MemoryStorage {
+                snapshot_every,
+                ..MemoryStorage::default()
+            };
+            run(interrupted_at, &mut storage);
+            assert_eq!(run(usize::MAX, &mut storage), expected());
+        }
+    }
+}
+
+#[test]
+fn disk_storage_resume() {
+    let dir = temp_dir("resume");
+    let mut storage = DiskStorage::open(&dir, 3).unwrap();
+    run(8, &mut storage);
+    drop(storage);
+
+    let mut storage = DiskStorage::open(&dir, 3).unwrap();
+    assert_eq!(run(usize::MAX, &mut storage), expected());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disk_storage_partial_entry() {
+    let dir = temp_dir("partial");
+    let mut storage = DiskStorage::open(&dir, 0).unwrap();
+    run(5, &mut storage);
+    drop(storage);
+
+    // an entry that was interrupted while being written
+    let mut wal = std::fs::OpenOptions::new()
+        .append(true)
+        .open(dir.join("wal.jsonl"))
+        .unwrap();
+    wal.write_all(b"{\"internal_txid\":5,\"tx\":{\"ty").unwrap();
+    drop(wal);
+
+    let mut storage = DiskStorage::open(&dir, 0).unwrap();
+    assert_eq!(run(usize::MAX, &mut storage), expected());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn memory_storage_rejected_expiry() {
+    // the rejected withdrawal still expires the hold
+    let input = "type, client, tx, amount, expires_at, timestamp
+deposit, 1, 1, 10, ,
+authorize, 1, 2, 10, 100,
+withdrawal, 2, 3, 5, , 100
+withdrawal, 1, 4, 10, ,
+";
+    let run = |storage: &mut MemoryStorage| {
+        let inputs = payment_engine::read_input(input.as_bytes());
+        payment_engine::run_rows_with_storage(inputs, &Policy::default(), storage, |_| Ok(()))
+            .unwrap()
+    };
+    let mut storage = MemoryStorage::default();
+    let clients = run(&mut storage);
+    assert_eq!(clients[&1.into()].total.to_string(), "0");
+    assert_eq!(run(&mut storage), clients);
+}
+
+#[test]
+fn memory_storage_resume_rejects() {
+    // the rejected rows aren't rejected again when resuming
+    for interrupted_at in 0..12 {
+        let mut storage = MemoryStorage::default();
+        let mut rejects = vec![];
+        for rows in [interrupted_at, usize::MAX] {
+            let inputs = payment_engine::read_input(INPUT.as_bytes()).take(rows);
+            payment_engine::run_rows_with_storage(inputs, &Policy::default(), &mut storage, |r| {
+                rejects.push(r.line);
+                Ok(())
+            })
+            .unwrap();
+        }
+        assert_eq!(rejects, vec![7, 10]);
+    }
+}
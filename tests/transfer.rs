@@ -0,0 +1,110 @@
+use payment_engine::{parallel, AccountStatus, ExternalTx, Policy};
+use rust_decimal::Decimal;
+
+mod common;
+use common::{b, balances, engine_with, transfer_tx as tx};
+
+/// The txs of a client 1 that transferred 4 out of 10 into client 2.
+fn transferred() -> [ExternalTx; 2] {
+    [tx("deposit, 1, 1, 10,"), tx("transfer, 1, 2, 4, 2")]
+}
+
+#[test]
+fn transfer_applied() {
+    let engine = engine_with(Policy::default(), &transferred());
+    assert_eq!(balances(&engine, 1), b("6", "0", "6"));
+    assert_eq!(balances(&engine, 2), b("4", "0", "4"));
+    assert_eq!(engine.tx(&2.into()).unwrap().destination, Some(2.into()));
+}
+
+#[test]
+fn transfer_atomic() {
+    let mut engine = engine_with(Policy::default(), &transferred());
+    let e = engine.process(&tx("transfer, 1, 3, 7, 2")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+
+    // the credit fails, so the debit isn't applied either
+    let max = Decimal::from_parts(u32::MAX, u32::MAX, u32::MAX, false, 0);
+    let mut deposit = tx("deposit, 3, 4, 1,");
+    deposit.amount = Some((max - Decimal::new(1, 0)).into());
+    engine.process(&deposit).unwrap();
+    let e = engine.process(&tx("transfer, 1, 5, 2, 3")).unwrap_err();
+    assert_eq!(e.code(), "E_AMOUNT_OVERFLOW");
+    assert_eq!(balances(&engine, 1), b("6", "0", "6"));
+    assert!(engine.tx(&5.into()).is_none());
+
+    let e = engine.process(&tx("transfer, 1, 6, 1,")).unwrap_err();
+    assert_eq!(e.code(), "E_MISSING_DESTINATION");
+    let e = engine.process(&tx("transfer, 1, 6, 1, 1")).unwrap_err();
+    assert_eq!(e.code(), "E_SELF_TRANSFER");
+    assert_eq!(balances(&engine, 1), b("6", "0", "6"));
+}
+
+#[test]
+fn transfer_duplicate_not_applied() {
+    let mut engine = engine_with(Policy::default(), &transferred());
+    let e = engine.process(&tx("transfer, 1, 2, 4, 2")).unwrap_err();
+    assert_eq!(e.code(), "E_DUPLICATE_TX");
+    let e = engine.process(&tx("transfer, 1, 1, 4, 2")).unwrap_err();
+    assert_eq!(e.code(), "E_DUPLICATE_TX");
+    let e = engine.process(&tx("deposit, 2, 2, 4,")).unwrap_err();
+    assert_eq!(e.code(), "E_DUPLICATE_TX");
+    assert_eq!(balances(&engine, 1), b("6", "0", "6"));
+    assert_eq!(balances(&engine, 2), b("4", "0", "4"));
+}
+
+#[test]
+fn transfer_status() {
+    let mut engine = engine_with(Policy::default(), &transferred());
+    let mut freeze = tx("freeze, 2, 3,,");
+    freeze.reason = Some("investigation".into());
+    engine.process(&freeze).unwrap();
+    let e = engine.process(&tx("transfer, 1, 4, 1, 2")).unwrap_err();
+    assert_eq!(e.code(), "E_FROZEN_CLIENT");
+    assert_eq!(balances(&engine, 1), b("6", "0", "6"));
+}
+
+#[test]
+fn transfer_dispute() {
+    let mut engine = engine_with(Policy::default(), &transferred());
+
+    // disputed by the source, holding the funds of the destination
+    engine.process(&tx("dispute, 1, 2,,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("6", "0", "6"));
+    assert_eq!(balances(&engine, 2), b("0", "4", "4"));
+    let e = engine.process(&tx("transfer, 2, 3, 1, 1")).unwrap_err();
+    assert_eq!(e.code(), "E_INSUFFICIENT_FUNDS");
+
+    engine.process(&tx("resolve, 1, 2,,")).unwrap();
+    assert_eq!(balances(&engine, 2), b("4", "0", "4"));
+
+    // the destination can't dispute it
+    let e = engine.process(&tx("dispute, 2, 2,,")).unwrap_err();
+    assert_eq!(e.code(), "E_DIFFERENT_CLIENT");
+
+    // the chargeback returns the funds, and locks the destination
+    engine.process(&tx("dispute, 1, 2,,")).unwrap();
+    engine.process(&tx("chargeback, 1, 2,,")).unwrap();
+    assert_eq!(balances(&engine, 1), b("10", "0", "10"));
+    assert_eq!(balances(&engine, 2), b("0", "0", "0"));
+    let status = |client: u16| engine.client(&client.into()).unwrap().status;
+    assert_eq!(status(1), AccountStatus::Active);
+    assert_eq!(status(2), AccountStatus::Locked);
+}
+
+#[test]
+fn transfer_parallel_unsupported() {
+    let input = "type, client, tx, amount, destination
+deposit, 1, 1, 10,
+transfer, 1, 2, 4, 2
+";
+    let mut codes = vec![];
+    let rows = payment_engine::read_input(input.as_bytes());
+    let clients = parallel::run_rows(rows, 2, &Policy::default(), |reject| {
+        codes.push(reject.code);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(codes, vec!["E_TRANSFER_UNSUPPORTED"]);
+    assert_eq!(clients[&1.into()].total.to_string(), "10");
+}
@@ -0,0 +1,40 @@
+use payment_engine::{
+    tx::{DuplicateTxIdError, Tx, TxType},
+    ExternalTx, Txs,
+};
+
+fn tx(txid: u32, internal_txid: u32) -> Tx {
+    let extx = ExternalTx {
+        ty: TxType::Deposit,
+        client: 1.into(),
+        txid: txid.into(),
+        amount: None,
+        destination: None,
+        asset: None,
+        reason: None,
+        expires_at: None,
+        timestamp: None,
+        row: None,
+    };
+    Tx::from_external(&extx, internal_txid.into())
+}
+
+#[test]
+fn txs_unordered() {
+    let mut txs = Txs::default();
+    for txid in &[7, 2, 9, 1] {
+        txs.insert(tx(*txid, 0)).unwrap();
+    }
+    assert_eq!(txs.len(), 4);
+    assert!(txs.contains(&2.into()));
+    assert!(txs.get(&3.into()).is_none());
+}
+
+#[test]
+fn txs_duplicate() {
+    let mut txs = Txs::default();
+    txs.insert(tx(7, 0)).unwrap();
+    assert_eq!(txs.insert(tx(7, 1)), Err(DuplicateTxIdError(7.into())));
+    // the first one is kept
+    assert_eq!(txs.get(&7.into()).unwrap().internal_txid, 0.into());
+}